@@ -0,0 +1,107 @@
+//! Throughput of sequential processing against `parallel::process_parallel` with an increasing
+//! number of workers. Run with `cargo bench --bench parallel`; the number of transactions can be
+//! changed by passing it as an argument.
+
+use std::env;
+use std::time::{Duration, Instant};
+
+use transactions::{
+    parallel,
+    process::{self, Policy},
+    Money, State, Transaction, TransactionType,
+};
+
+// small xorshift generator so that the workload is the same on every run
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}
+
+// mostly deposits and withdrawals spread over many clients, with some disputes of earlier deposits
+// and transfers between clients
+fn workload(n: u32, clients: u16) -> Vec<Transaction> {
+    let mut rng = Rng(0x2545F4914F6CDD1D);
+    let mut last_deposit = vec![None; clients as usize];
+
+    (0..n)
+        .map(|tx| {
+            let client = (rng.next() % clients as u64) as u16;
+            match (rng.next() % 20, last_deposit[client as usize]) {
+                (0, Some(deposit)) => {
+                    Transaction::new(TransactionType::Dispute, client, deposit, None)
+                }
+                (6, _) => Transaction {
+                    to_client: Some((rng.next() % clients as u64) as u16),
+                    ..Transaction::new(
+                        TransactionType::Transfer,
+                        client,
+                        tx,
+                        Some(Money::from_units((rng.next() % 100000) as i64)),
+                    )
+                },
+                (1..=5, _) => Transaction::new(
+                    TransactionType::Withdrawal,
+                    client,
+                    tx,
+                    Some(Money::from_units((rng.next() % 100000) as i64)),
+                ),
+                _ => {
+                    last_deposit[client as usize] = Some(tx);
+                    Transaction::new(
+                        TransactionType::Deposit,
+                        client,
+                        tx,
+                        Some(Money::from_units((rng.next() % 1000000) as i64)),
+                    )
+                }
+            }
+        })
+        .collect()
+}
+
+fn report(name: &str, n: usize, elapsed: Duration) {
+    println!(
+        "{:<12} {:>10.0} tx/s ({:?})",
+        name,
+        n as f64 / elapsed.as_secs_f64(),
+        elapsed
+    );
+}
+
+fn main() {
+    let n = env::args()
+        .skip(1)
+        .find_map(|arg| arg.parse().ok())
+        .unwrap_or(4_000_000);
+    let transactions = workload(n, u16::MAX);
+
+    let input = transactions.clone();
+    let start = Instant::now();
+    let mut state = State::new();
+    for t in input {
+        let _ = process::process_one(&mut state, t);
+    }
+    report("sequential", transactions.len(), start.elapsed());
+
+    for workers in [1, 2, 4, 8] {
+        let input = transactions.clone();
+        let start = Instant::now();
+        let _ = parallel::process_parallel(
+            input.into_iter().map(|t| ((), t)),
+            workers,
+            Policy::default(),
+            None,
+        );
+        report(
+            &format!("{} workers", workers),
+            transactions.len(),
+            start.elapsed(),
+        );
+    }
+}
@@ -1,129 +1,269 @@
-use serde::{Serialize, Serializer};
+use std::collections::BTreeMap;
 use thiserror::Error;
 
-use crate::transaction::TransactionType;
+use crate::{currency::Currency, money::Money, transaction::TransactionType};
 
-fn i64_as_money_string(mut val: i64) -> String {
-    let mut negative = false;
-    if val < 0 {
-        negative = true;
-        val *= -1;
-    }
-
-    let whole = val / 10000;
-    let fractional = val % 10000;
-
-    format!(
-        "{}{}.{}",
-        if negative { "-" } else { "" },
-        whole,
-        format!("{:0>4}", fractional)
-    )
-}
-
-fn amount_serializer<S: Serializer>(val: &i64, s: S) -> Result<S::Ok, S::Error> {
-    s.serialize_str(&i64_as_money_string(*val))
-}
-
-#[derive(Debug, PartialEq, Serialize)]
+/// A client's account, with separate balances in each currency it holds. Locking applies to the
+/// whole account.
+#[derive(Clone, Debug, PartialEq)]
 pub struct Account {
-    #[serde(rename = "client")]
     pub id: u16,
-    #[serde(serialize_with = "amount_serializer")]
-    pub available: i64,
-    #[serde(serialize_with = "amount_serializer")]
-    pub held: i64,
-    #[serde(serialize_with = "amount_serializer")]
-    pub total: i64,
+    /// Balances by currency. Currencies with nothing in them are left out, so an account that has
+    /// never held anything has no balances at all.
+    pub balances: BTreeMap<Currency, Balance>,
     pub locked: bool,
 }
 
+/// An account's funds in one currency.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Balance {
+    pub available: Money,
+    pub held: Money,
+    pub total: Money,
+}
+
 #[derive(Debug, PartialEq, Error)]
 pub enum AccountError {
     #[error("not enough available")]
     NotEnoughAvailable,
+    #[error("balance would overflow")]
+    BalanceOverflow,
+    #[error("only a deposit, withdrawal, conversion or transfer can be charged back")]
+    NotChargeable,
 }
 use AccountError::*;
 
+impl AccountError {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            NotEnoughAvailable => "NotEnoughAvailable",
+            BalanceOverflow => "BalanceOverflow",
+            NotChargeable => "NotChargeable",
+        }
+    }
+}
+
+impl Balance {
+    pub fn is_zero(&self) -> bool {
+        *self == Balance::default()
+    }
+
+    fn deposit(&mut self, amount: Money) -> Result<(), AccountError> {
+        let available = add(self.available, amount)?;
+        self.total = add(self.total, amount)?;
+        self.available = available;
+
+        Ok(())
+    }
+
+    fn withdraw(&mut self, amount: Money) -> Result<(), AccountError> {
+        let available = sub(self.available, amount)?;
+        self.total = sub(self.total, amount)?;
+        self.available = available;
+
+        Ok(())
+    }
+
+    fn hold(&mut self, amount: Money) -> Result<(), AccountError> {
+        let available = sub(self.available, amount)?;
+        self.held = add(self.held, amount)?;
+        self.available = available;
+
+        Ok(())
+    }
+
+    fn release(&mut self, amount: Money) -> Result<(), AccountError> {
+        let available = add(self.available, amount)?;
+        self.held = sub(self.held, amount)?;
+        self.available = available;
+
+        Ok(())
+    }
+}
+
 impl Account {
     pub fn new(id: u16) -> Self {
         Account {
             id,
-            available: 0,
-            held: 0,
-            total: 0,
+            balances: BTreeMap::new(),
             locked: false,
         }
     }
 
-    pub fn deposit(&mut self, amount: u64) {
-        self.available += amount as i64;
-        self.total += amount as i64;
+    /// An account holding `balance` in the default currency only.
+    pub fn with_balance(id: u16, balance: Balance, locked: bool) -> Self {
+        let mut account = Account::new(id);
+        account.set_balance(Currency::DEFAULT, balance);
+        account.locked = locked;
+
+        account
     }
 
-    pub fn withdraw(&mut self, amount: u64) -> Result<(), AccountError> {
-        if self.available < amount as i64 {
-            Err(NotEnoughAvailable)
-        } else {
-            self.force_withdraw(amount);
+    /// The account's funds in `currency`, all zero if it has never held any.
+    pub fn balance(&self, currency: Currency) -> Balance {
+        self.balances.get(&currency).copied().unwrap_or_default()
+    }
 
-            Ok(())
+    pub fn set_balance(&mut self, currency: Currency, balance: Balance) {
+        if balance.is_zero() {
+            self.balances.remove(&currency);
+        } else {
+            self.balances.insert(currency, balance);
         }
     }
 
-    fn force_withdraw(&mut self, amount: u64) {
-        self.available -= amount as i64;
-        self.total -= amount as i64;
+    // every method leaves the account as it was if it fails, which `update` takes care of by
+    // working on a copy of the balance
+
+    fn update<F>(&mut self, currency: Currency, f: F) -> Result<(), AccountError>
+    where
+        F: FnOnce(&mut Balance) -> Result<(), AccountError>,
+    {
+        let mut balance = self.balance(currency);
+        f(&mut balance)?;
+        self.set_balance(currency, balance);
+
+        Ok(())
     }
 
-    fn hold(&mut self, amount: u64) {
-        self.available -= amount as i64;
-        self.held += amount as i64;
+    pub fn deposit(&mut self, currency: Currency, amount: Money) -> Result<(), AccountError> {
+        self.update(currency, |b| b.deposit(amount))
     }
 
-    fn release(&mut self, amount: u64) {
-        self.available += amount as i64;
-        self.held -= amount as i64;
+    pub fn withdraw(&mut self, currency: Currency, amount: Money) -> Result<(), AccountError> {
+        self.update(currency, |b| {
+            if b.available < amount {
+                Err(NotEnoughAvailable)
+            } else {
+                b.withdraw(amount)
+            }
+        })
     }
 
-    pub fn dispute(&mut self, amount: u64, transaction_type: TransactionType) {
-        if let TransactionType::Deposit = transaction_type {
-            self.hold(amount);
-        } else {
-            self.release(amount);
-        }
+    /// Moves funds from available to held, as disputing a deposit does.
+    pub fn hold(&mut self, currency: Currency, amount: Money) -> Result<(), AccountError> {
+        self.update(currency, |b| b.hold(amount))
     }
 
-    pub fn resolve(&mut self, amount: u64, transaction_type: TransactionType) {
-        if let TransactionType::Deposit = transaction_type {
-            self.release(amount);
-        } else {
-            self.hold(amount);
-        }
+    /// Moves funds from held to available, as resolving a deposit's dispute does.
+    pub fn release(&mut self, currency: Currency, amount: Money) -> Result<(), AccountError> {
+        self.update(currency, |b| b.release(amount))
     }
 
-    pub fn chargeback(&mut self, amount: u64, transaction_type: TransactionType) {
-        self.resolve(amount, transaction_type);
+    pub fn chargeback(
+        &mut self,
+        currency: Currency,
+        amount: Money,
+        transaction_type: TransactionType,
+    ) -> Result<(), AccountError> {
+        self.update(currency, |b| match transaction_type {
+            // a conversion credited the currency it is charged back in, and a transfer the account
+            // it is charged back from, like a deposit
+            TransactionType::Deposit | TransactionType::Convert | TransactionType::Transfer => {
+                b.release(amount)?;
+                b.withdraw(amount)
+            }
+            TransactionType::Withdrawal => {
+                b.hold(amount)?;
+                b.deposit(amount)
+            }
+            _ => Err(NotChargeable),
+        })?;
+        self.locked = true;
 
-        match transaction_type {
-            TransactionType::Deposit => self.force_withdraw(amount),
-            TransactionType::Withdrawal => self.deposit(amount),
-            _ => unreachable!(),
-        }
+        Ok(())
+    }
 
+    /// Charges back a withdrawal whose amount was never held while it was disputed, crediting the
+    /// amount back and locking the account.
+    pub fn chargeback_unheld_withdrawal(
+        &mut self,
+        currency: Currency,
+        amount: Money,
+    ) -> Result<(), AccountError> {
+        self.deposit(currency, amount)?;
         self.locked = true;
+
+        Ok(())
     }
 }
 
+fn add(balance: Money, amount: Money) -> Result<Money, AccountError> {
+    balance.checked_add(amount).ok_or(BalanceOverflow)
+}
+
+fn sub(balance: Money, amount: Money) -> Result<Money, AccountError> {
+    balance.checked_sub(amount).ok_or(BalanceOverflow)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[test]
-    fn test_i64_as_money_string() {
-        assert_eq!(i64_as_money_string(10000), "1.0000");
-        assert_eq!(i64_as_money_string(-10000), "-1.0000");
-        assert_eq!(i64_as_money_string(-1234500), "-123.4500");
-        assert_eq!(i64_as_money_string(0), "0.0000");
+    fn overflow_leaves_account_unchanged() {
+        let mut account = Account::new(1);
+        account.deposit(Currency::DEFAULT, Money::MAX).unwrap();
+        let before = account.clone();
+
+        assert_eq!(
+            account.deposit(Currency::DEFAULT, Money::from_units(1)),
+            Err(BalanceOverflow)
+        );
+        assert_eq!(account, before);
+
+        // holding the amount again fits but crediting it back doesn't
+        assert_eq!(
+            account.chargeback(
+                Currency::DEFAULT,
+                Money::from_units(1),
+                TransactionType::Withdrawal
+            ),
+            Err(BalanceOverflow)
+        );
+        assert_eq!(account, before);
+    }
+
+    #[test]
+    fn only_stored_types_can_be_charged_back() {
+        let mut account = Account::new(1);
+        account
+            .deposit(Currency::DEFAULT, Money::from_units(10000))
+            .unwrap();
+        let before = account.clone();
+
+        assert_eq!(
+            account.chargeback(
+                Currency::DEFAULT,
+                Money::from_units(10000),
+                TransactionType::Lock
+            ),
+            Err(NotChargeable)
+        );
+        assert_eq!(account, before);
+    }
+
+    #[test]
+    fn currencies_are_separate() {
+        let eur = "EUR".parse().unwrap();
+        let mut account = Account::new(1);
+        account
+            .deposit(Currency::DEFAULT, Money::from_units(10000))
+            .unwrap();
+
+        assert_eq!(
+            account.withdraw(eur, Money::from_units(1)),
+            Err(NotEnoughAvailable)
+        );
+        // a failed change doesn't leave an empty balance behind
+        assert!(!account.balances.contains_key(&eur));
+
+        account.deposit(eur, Money::from_units(5000)).unwrap();
+        account.withdraw(eur, Money::from_units(5000)).unwrap();
+        assert!(!account.balances.contains_key(&eur));
+        assert_eq!(
+            account.balance(Currency::DEFAULT).total,
+            Money::from_units(10000)
+        );
     }
 }
@@ -0,0 +1,268 @@
+//! Checks of the engine's invariants, for finding bugs and corrupted state rather than bad input.
+//!
+//! With [`Policy::audit`](crate::process::Policy::audit) set, `process_one` checks the account and
+//! stored transaction each transaction touched, and fails with
+//! [`InvariantViolated`](crate::process::TransactionProcessingError::InvariantViolated) for the
+//! first transaction that breaks an invariant. [`check`] checks a whole state at once, such as one
+//! just restored from a snapshot.
+//!
+//! The invariants are, for each currency an account holds:
+//! - the total is the available plus held funds
+//! - the held funds are what the disputed transactions in that currency call for: the amount of
+//!   each of the account's disputed deposits and what each disputed conversion or transfer credited
+//!   it, less the amount of each of its disputed withdrawals under
+//!   [`WithdrawalDisputes::Release`](crate::process::WithdrawalDisputes::Release)
+//! - every stored transaction belongs to a client that has an account, as does the client of a
+//!   transfer
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::convert::TryFrom;
+use std::iter;
+use thiserror::Error;
+
+use crate::{
+    currency::Currency,
+    process::{self, Policy, State},
+    store::{AccountStore, StoreError, TransactionStore},
+    transaction::TransactionStatus,
+    Account, Money, StoredTransaction, Transaction, TransactionType,
+};
+
+#[derive(Clone, Debug, PartialEq, Error)]
+pub enum Violation {
+    #[error(
+        "client {client} has {available} available and {held} held but a total of {total}{}",
+        in_currency(.currency)
+    )]
+    Total {
+        client: u16,
+        currency: Currency,
+        available: Money,
+        held: Money,
+        total: Money,
+    },
+    #[error(
+        "client {client} holds {held} but its disputed transactions call for {expected}{}",
+        in_currency(.currency)
+    )]
+    Held {
+        client: u16,
+        currency: Currency,
+        held: Money,
+        expected: Money,
+    },
+    #[error(
+        "client {client} holds {held} but its disputed transactions call for more than a balance \
+         can hold{}",
+        in_currency(.currency)
+    )]
+    HeldOverflow {
+        client: u16,
+        currency: Currency,
+        held: Money,
+    },
+    #[error("transaction {tx} belongs to client {client}, who has no account")]
+    NoAccount { tx: u32, client: u16 },
+}
+use Violation::*;
+
+fn in_currency(currency: &Currency) -> String {
+    if currency.is_default() {
+        String::new()
+    } else {
+        format!(" in {}", currency)
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum AuditError {
+    #[error(transparent)]
+    Violation(#[from] Violation),
+    #[error("store error: {0}")]
+    Store(#[from] StoreError),
+}
+
+/// Checks every invariant across the whole of `state`, returning the first one that doesn't hold.
+pub fn check<T: TransactionStore, A: AccountStore>(state: &State<T, A>) -> Result<(), AuditError> {
+    // summed in units wide enough that no order of the transactions can overflow
+    let mut expected = HashMap::<u16, BTreeMap<Currency, i128>>::new();
+    for entry in state.transactions.entries() {
+        let (tx, t) = entry?;
+        check_accounts(state, tx, &t)?;
+        let (client, currency, held) = held_for(&state.policy, &t);
+        *expected
+            .entry(client)
+            .or_default()
+            .entry(currency)
+            .or_default() += i128::from(held.units());
+    }
+
+    for account in state.accounts.all() {
+        let called_for = expected.get(&account.id);
+        // a currency can be called for without the account holding anything in it
+        let currencies = account
+            .balances
+            .keys()
+            .chain(called_for.into_iter().flat_map(|c| c.keys()))
+            .copied()
+            .collect::<BTreeSet<_>>();
+        for currency in currencies {
+            let held = called_for.and_then(|c| c.get(&currency)).copied();
+            let expected = i64::try_from(held.unwrap_or_default())
+                .map(Money::from_units)
+                .map_err(|_| HeldOverflow {
+                    client: account.id,
+                    currency,
+                    held: account.balance(currency).held,
+                })?;
+            check_balance(account, currency, expected)?;
+        }
+    }
+
+    Ok(())
+}
+
+// the accounts and stored transaction a transaction may touch, from just before it is applied
+pub(crate) struct Before {
+    tx: u32,
+    accounts: Vec<(u16, Option<Account>)>,
+    stored: Option<StoredTransaction>,
+}
+
+impl Before {
+    pub(crate) fn take<T: TransactionStore, A: AccountStore>(
+        state: &State<T, A>,
+        t: &Transaction,
+    ) -> Result<Self, StoreError> {
+        let stored = state.transactions.get(t.tx)?;
+        // a transfer, or a dispute of one, also touches the client it is to
+        let mut clients = vec![t.client];
+        clients.extend(t.to_client);
+        clients.extend(stored.and_then(|s| s.to_client));
+        clients.sort_unstable();
+        clients.dedup();
+
+        let accounts = clients
+            .into_iter()
+            .map(|client| Ok((client, state.accounts.get(client)?)))
+            .collect::<Result<_, StoreError>>()?;
+
+        Ok(Before {
+            tx: t.tx,
+            accounts,
+            stored,
+        })
+    }
+
+    // checks what the transaction touched, assuming everything held before it was applied
+    pub(crate) fn check<T: TransactionStore, A: AccountStore>(
+        self,
+        state: &State<T, A>,
+    ) -> Result<(), AuditError> {
+        let stored = state.transactions.get(self.tx)?;
+        // a stored transaction the transaction left alone is assumed to hold up as before
+        if let Some(stored) = stored.as_ref().filter(|&t| self.stored.as_ref() != Some(t)) {
+            check_accounts(state, self.tx, stored)?;
+        }
+
+        // what the stored transaction holds of a client's funds in a currency
+        let held_of = |t: &Option<StoredTransaction>, client: u16, currency: Currency| match t {
+            Some(t) => match held_for(&state.policy, t) {
+                (k, c, held) if k == client && c == currency => held,
+                _ => Money::ZERO,
+            },
+            None => Money::ZERO,
+        };
+
+        for (client, before) in self.accounts {
+            let account = match state.accounts.get(client)? {
+                Some(account) => account,
+                None => continue,
+            };
+            let currencies = account
+                .balances
+                .keys()
+                .chain(before.iter().flat_map(|a| a.balances.keys()))
+                .copied()
+                .chain(
+                    self.stored
+                        .iter()
+                        .chain(stored.iter())
+                        .map(|t| held_for(&state.policy, t))
+                        .filter(|(k, _, _)| *k == client)
+                        .map(|(_, currency, _)| currency),
+                )
+                .collect::<BTreeSet<_>>();
+
+            for currency in currencies {
+                let held = before
+                    .as_ref()
+                    .map_or(Money::ZERO, |a| a.balance(currency).held);
+                let change = held_of(&stored, client, currency).saturating_sub(held_of(
+                    &self.stored,
+                    client,
+                    currency,
+                ));
+                check_balance(&account, currency, held.saturating_add(change))?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// every client a stored transaction names has to have an account
+fn check_accounts<T: TransactionStore, A: AccountStore>(
+    state: &State<T, A>,
+    tx: u32,
+    t: &StoredTransaction,
+) -> Result<(), AuditError> {
+    for client in iter::once(t.client).chain(t.to_client) {
+        if state.accounts.get(client)?.is_none() {
+            return Err(NoAccount { tx, client }.into());
+        }
+    }
+
+    Ok(())
+}
+
+fn check_balance(account: &Account, currency: Currency, expected: Money) -> Result<(), Violation> {
+    let balance = account.balance(currency);
+    if balance.available.checked_add(balance.held) != Some(balance.total) {
+        return Err(Total {
+            client: account.id,
+            currency,
+            available: balance.available,
+            held: balance.held,
+            total: balance.total,
+        });
+    }
+    if balance.held != expected {
+        return Err(Held {
+            client: account.id,
+            currency,
+            held: balance.held,
+            expected,
+        });
+    }
+
+    Ok(())
+}
+
+// what a stored transaction adds to a client's held funds, and whose and in which currency. These
+// can be negative, so a sum of them can pass the limit of a balance partway and still end up
+// within it; `check` sums them in i128 so that the order they come in doesn't matter.
+fn held_for(policy: &Policy, t: &StoredTransaction) -> (u16, Currency, Money) {
+    if t.status != TransactionStatus::Disputed {
+        return (t.client, t.currency, Money::ZERO);
+    }
+
+    match (t.r#type, t.converted, t.to_client) {
+        (TransactionType::Deposit, _, _) => (t.client, t.currency, t.amount),
+        // a disputed conversion or transfer holds what it credited
+        (_, Some(converted), _) => (t.client, converted.currency, converted.amount),
+        (_, _, Some(to_client)) => (to_client, t.currency, t.amount),
+        _ if process::holds_nothing(policy, t) => (t.client, t.currency, Money::ZERO),
+        _ => (t.client, t.currency, Money::ZERO.saturating_sub(t.amount)),
+    }
+}
@@ -0,0 +1,145 @@
+use std::collections::HashMap;
+use std::iter;
+use std::mem;
+use thiserror::Error;
+
+use crate::{
+    process::{self, State, TransactionProcessingError},
+    store::{AccountStore, StoreError, TransactionStore},
+    Account, StoredTransaction, Transaction,
+};
+
+#[derive(PartialEq, Debug, Error)]
+pub enum BatchError {
+    /// The transaction at `index` failed and the batch was rolled back.
+    #[error("transaction {index} of the batch failed: {source}")]
+    TransactionFailed {
+        index: usize,
+        source: TransactionProcessingError,
+    },
+    /// The transaction at `index` failed, and putting the state back failed too, so the state may
+    /// be left partly changed.
+    #[error("could not roll back the batch after transaction {index} failed: {source}")]
+    RollbackFailed { index: usize, source: StoreError },
+    /// Every transaction was applied, but the batch's journal entries could not be written.
+    #[error("could not write the journal for the batch: {source}")]
+    CommitFailed { source: StoreError },
+}
+
+/// Applies every transaction or none of them.
+///
+/// If a transaction fails, the accounts and stored transactions are put back exactly as they were
+/// before the batch, and the error is returned along with the index of the failing transaction.
+pub fn process_batch<T, A, I>(state: &mut State<T, A>, transactions: I) -> Result<(), BatchError>
+where
+    T: TransactionStore,
+    A: AccountStore,
+    I: IntoIterator<Item = Transaction>,
+{
+    let mut batch = Batch::new(state);
+    for (index, t) in transactions.into_iter().enumerate() {
+        if let Err(source) = batch.process(t) {
+            return match batch.rollback() {
+                Ok(()) => Err(BatchError::TransactionFailed { index, source }),
+                Err(source) => Err(BatchError::RollbackFailed { index, source }),
+            };
+        }
+    }
+
+    batch
+        .commit()
+        .map_err(|source| BatchError::CommitFailed { source })
+}
+
+/// A group of transactions applied to a `State` that can be undone as a whole until it is
+/// committed.
+///
+/// Before each transaction, the accounts and stored transaction it may touch are copied into an
+/// undo log, so the log grows with the number of distinct accounts and transaction ids in the
+/// batch. Journal entries are held back until the batch is committed, and thrown away if it is
+/// rolled back. Dropping a batch without rolling it back keeps its changes, the same as committing it.
+pub struct Batch<'a, T, A> {
+    state: &'a mut State<T, A>,
+    // the value from before the batch, if any, of everything it has touched
+    accounts: HashMap<u16, Option<Account>>,
+    transactions: HashMap<u32, Option<StoredTransaction>>,
+}
+
+impl<'a, T: TransactionStore, A: AccountStore> Batch<'a, T, A> {
+    pub fn new(state: &'a mut State<T, A>) -> Self {
+        if let Some(journal) = state.journal.as_mut() {
+            journal.begin_batch();
+        }
+
+        Batch {
+            state,
+            accounts: HashMap::new(),
+            transactions: HashMap::new(),
+        }
+    }
+
+    /// Applies one transaction with [`process_one`](process::process_one). A transaction that
+    /// fails leaves the state as it was, so the batch can carry on if the caller wants it to.
+    pub fn process(&mut self, t: Transaction) -> Result<(), TransactionProcessingError> {
+        if !self.transactions.contains_key(&t.tx) {
+            let stored = self.state.transactions.get(t.tx)?;
+            self.transactions.insert(t.tx, stored);
+        }
+        // a transfer also touches the client it is to, and so do disputes of it. A transfer made
+        // earlier in the batch recorded that client then, so the stored transaction from before
+        // the batch is enough to go on.
+        let transferred_to = self.transactions[&t.tx].and_then(|stored| stored.to_client);
+        for client in iter::once(t.client)
+            .chain(t.to_client)
+            .chain(transferred_to)
+        {
+            if !self.accounts.contains_key(&client) {
+                let account = self.state.accounts.get(client)?;
+                self.accounts.insert(client, account);
+            }
+        }
+
+        process::process_one(self.state, t)
+    }
+
+    /// Keeps every change made by the batch, and writes out its journal entries.
+    pub fn commit(self) -> Result<(), StoreError> {
+        match self.state.journal.as_mut() {
+            Some(journal) => {
+                journal.end_batch();
+                journal.flush()
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Puts every account and stored transaction touched by the batch back as it was.
+    pub fn rollback(mut self) -> Result<(), StoreError> {
+        if let Some(journal) = self.state.journal.as_mut() {
+            journal.discard_batch();
+        }
+
+        for (client, account) in mem::take(&mut self.accounts) {
+            match account {
+                Some(account) => self.state.accounts.put(account)?,
+                None => self.state.accounts.remove(client)?,
+            }
+        }
+        for (id, stored) in mem::take(&mut self.transactions) {
+            match stored {
+                Some(t) => self.state.transactions.put(id, t)?,
+                None => self.state.transactions.remove(id)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl<T, A> Drop for Batch<'_, T, A> {
+    fn drop(&mut self) {
+        if let Some(journal) = self.state.journal.as_mut() {
+            journal.end_batch();
+        }
+    }
+}
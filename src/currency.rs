@@ -0,0 +1,129 @@
+//! Currency codes.
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::str::FromStr;
+use thiserror::Error;
+
+const MAX_LEN: usize = 8;
+
+/// The currency of an amount, as a code of up to eight ASCII letters and digits such as `EUR`.
+/// Codes are kept in upper case, so `eur` and `EUR` are the same currency.
+///
+/// Input without a currency is in [`Currency::DEFAULT`], which has an empty code. It is written
+/// as an empty string and left out wherever it can be, so output for input without currencies
+/// looks the same as it always has.
+#[derive(Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Currency([u8; MAX_LEN]);
+
+#[derive(Debug, PartialEq, Error)]
+#[error("currency {0:?} is not up to eight letters and digits")]
+pub struct ParseCurrencyError(String);
+
+impl Currency {
+    pub const DEFAULT: Self = Currency([0; MAX_LEN]);
+
+    pub fn is_default(&self) -> bool {
+        *self == Self::DEFAULT
+    }
+
+    pub fn as_str(&self) -> &str {
+        let len = self.0.iter().position(|&b| b == 0).unwrap_or(MAX_LEN);
+        // only ever built from ASCII
+        std::str::from_utf8(&self.0[..len]).unwrap()
+    }
+
+    /// The raw bytes of the code, zero padded, for fixed-width storage.
+    pub fn to_bytes(self) -> [u8; MAX_LEN] {
+        self.0
+    }
+
+    /// Reads back bytes written by [`to_bytes`](Self::to_bytes), or `None` if they aren't a code.
+    pub fn from_bytes(bytes: [u8; MAX_LEN]) -> Option<Self> {
+        let len = bytes.iter().position(|&b| b == 0).unwrap_or(MAX_LEN);
+        let valid = bytes[..len]
+            .iter()
+            .all(|b| b.is_ascii_uppercase() || b.is_ascii_digit())
+            && bytes[len..].iter().all(|&b| b == 0);
+
+        if valid {
+            Some(Currency(bytes))
+        } else {
+            None
+        }
+    }
+}
+
+impl fmt::Display for Currency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl fmt::Debug for Currency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Currency({:?})", self.as_str())
+    }
+}
+
+impl FromStr for Currency {
+    type Err = ParseCurrencyError;
+
+    /// Parses a currency code, or the default currency from an empty string.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let code = s.trim();
+        if code.len() > MAX_LEN || !code.bytes().all(|b| b.is_ascii_alphanumeric()) {
+            return Err(ParseCurrencyError(s.to_string()));
+        }
+
+        let mut bytes = [0; MAX_LEN];
+        for (byte, c) in bytes.iter_mut().zip(code.bytes()) {
+            *byte = c.to_ascii_uppercase();
+        }
+
+        Ok(Currency(bytes))
+    }
+}
+
+impl Serialize for Currency {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for Currency {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        let buf = String::deserialize(d)?;
+        buf.parse().map_err(de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let eur = "EUR".parse::<Currency>().unwrap();
+        assert_eq!(eur.to_string(), "EUR");
+        assert_eq!("eur".parse::<Currency>(), Ok(eur));
+        assert_eq!(" EUR ".parse::<Currency>(), Ok(eur));
+        assert_eq!("".parse::<Currency>(), Ok(Currency::DEFAULT));
+        assert_eq!("USDC1234".parse::<Currency>().unwrap().as_str(), "USDC1234");
+        assert!("USDC12345".parse::<Currency>().is_err());
+        assert!("E-R".parse::<Currency>().is_err());
+        assert!("€".parse::<Currency>().is_err());
+    }
+
+    #[test]
+    fn bytes_round_trip() {
+        let eur = "EUR".parse::<Currency>().unwrap();
+        assert_eq!(Currency::from_bytes(eur.to_bytes()), Some(eur));
+        assert_eq!(
+            Currency::from_bytes(Currency::DEFAULT.to_bytes()),
+            Some(Currency::DEFAULT)
+        );
+        assert_eq!(Currency::from_bytes(*b"E\0R\0\0\0\0\0"), None);
+        assert_eq!(Currency::from_bytes(*b"eur\0\0\0\0\0"), None);
+    }
+}
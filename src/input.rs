@@ -0,0 +1,243 @@
+use std::convert::TryFrom;
+use std::io::{BufRead, BufReader, Read};
+use std::path::Path;
+use std::str::FromStr;
+use thiserror::Error;
+
+use csv::StringRecord;
+use serde_json::value::RawValue;
+
+use crate::{reject::Rejection, Transaction};
+
+/// Formats transactions can be read from.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    /// CSV with a header row, e.g. `type,client,tx,amount`.
+    Csv,
+    /// A single JSON array of transaction objects.
+    Json,
+    /// One JSON transaction object per line.
+    JsonLines,
+}
+
+impl Format {
+    /// Guesses the format from a file extension, defaulting to CSV.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Self {
+        match path.as_ref().extension().and_then(|e| e.to_str()) {
+            Some("json") => Format::Json,
+            Some("jsonl") | Some("ndjson") => Format::JsonLines,
+            _ => Format::Csv,
+        }
+    }
+}
+
+impl FromStr for Format {
+    type Err = InputError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(Format::Csv),
+            "json" => Ok(Format::Json),
+            "jsonl" | "ndjson" => Ok(Format::JsonLines),
+            _ => Err(InputError::UnknownFormat(s.to_string())),
+        }
+    }
+}
+
+/// Errors that stop the input from being read at all, as opposed to individual bad rows.
+#[derive(Debug, Error)]
+pub enum InputError {
+    #[error("unknown input format {0}")]
+    UnknownFormat(String),
+    #[error("could not read CSV: {0}")]
+    Csv(#[from] csv::Error),
+    #[error("could not read JSON: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+/// Where a transaction came from in the input.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Record {
+    /// Line number, for line-oriented formats.
+    pub line: Option<u64>,
+    /// The record as it appeared in the input.
+    pub raw: String,
+}
+
+/// Every row of the input, either as a transaction or as a rejection saying why it could not be
+/// read.
+pub type Rows = Box<dyn Iterator<Item = Result<(Record, Transaction), Rejection>>>;
+
+/// Reads transactions in the given format. Rows that can't be read are passed through as
+/// rejections so that the rest of the input can still be processed.
+pub fn read<R: Read + 'static>(reader: R, format: Format) -> Result<Rows, InputError> {
+    match format {
+        Format::Csv => read_csv(reader),
+        Format::Json => read_json(reader),
+        Format::JsonLines => Ok(read_json_lines(reader)),
+    }
+}
+
+fn read_csv<R: Read + 'static>(reader: R) -> Result<Rows, InputError> {
+    // flexible so that rows with the wrong number of fields still come through as records and can
+    // be reported verbatim
+    let mut rdr = csv::ReaderBuilder::new().flexible(true).from_reader(reader);
+    let headers = rdr.headers()?.clone();
+
+    Ok(Box::new(rdr.into_records().map(move |result| {
+        let field = |record: &StringRecord, name: &str| {
+            let idx = headers.iter().position(|h| h.trim() == name)?;
+            record.get(idx).map(|f| f.trim().to_string())
+        };
+
+        let record = result.map_err(|e| {
+            let line = e.position().map(|p| p.line());
+            Rejection::invalid_record(line, String::new(), None, None, e.to_string())
+        })?;
+        let line = record.position().map(|p| p.line());
+        let raw = record.iter().collect::<Vec<_>>().join(",");
+
+        match record.deserialize::<Transaction>(Some(&headers)) {
+            Ok(t) => Ok((Record { line, raw }, t)),
+            Err(e) => Err(Rejection::invalid_record(
+                line,
+                raw,
+                field(&record, "tx").and_then(|tx| tx.parse().ok()),
+                field(&record, "client").and_then(|client| client.parse().ok()),
+                e.to_string(),
+            )),
+        }
+    })))
+}
+
+// turns one JSON object into a transaction, recovering the tx and client on a best-effort basis if
+// it isn't a valid one
+fn parse_json(line: Option<u64>, raw: &str) -> Result<(Record, Transaction), Rejection> {
+    let record = Record {
+        line,
+        raw: raw.to_string(),
+    };
+
+    match Transaction::from_json(raw) {
+        Ok(t) => Ok((record, t)),
+        Err(e) => {
+            let value = serde_json::from_str::<serde_json::Value>(raw).ok();
+            let field = |name: &str| value.as_ref()?.get(name)?.as_u64();
+
+            Err(Rejection::invalid_record(
+                record.line,
+                record.raw,
+                field("tx").and_then(|tx| u32::try_from(tx).ok()),
+                field("client").and_then(|client| u16::try_from(client).ok()),
+                e.to_string(),
+            ))
+        }
+    }
+}
+
+fn read_json<R: Read>(reader: R) -> Result<Rows, InputError> {
+    // the array has to be read in full to know it's well formed, but each element is kept as its
+    // original text so that it can be parsed, and reported, on its own
+    let elements: Vec<Box<RawValue>> = serde_json::from_reader(BufReader::new(reader))?;
+
+    Ok(Box::new(
+        elements
+            .into_iter()
+            .map(|element| parse_json(None, element.get())),
+    ))
+}
+
+fn read_json_lines<R: Read + 'static>(reader: R) -> Rows {
+    Box::new(
+        BufReader::new(reader)
+            .lines()
+            .enumerate()
+            .filter_map(|(i, line)| {
+                let line_number = Some(i as u64 + 1);
+                match line {
+                    Err(e) => Some(Err(Rejection::invalid_record(
+                        line_number,
+                        String::new(),
+                        None,
+                        None,
+                        e.to_string(),
+                    ))),
+                    Ok(line) if line.trim().is_empty() => None,
+                    Ok(line) => Some(parse_json(line_number, line.trim())),
+                }
+            }),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Money;
+
+    fn amounts(input: &'static str, format: Format) -> Vec<Result<Option<i64>, Option<u32>>> {
+        read(input.as_bytes(), format)
+            .unwrap()
+            .map(|row| {
+                row.map(|(_, t)| t.amount.map(Money::units))
+                    .map_err(|r| r.tx)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_formats_agree() {
+        let csv = "type,client,tx,amount\n\
+                   deposit,1,1,1.5\n\
+                   deposit,1,2,1.00001\n\
+                   dispute,1,1,\n";
+        let json = r#"[
+            {"type": "deposit", "client": 1, "tx": 1, "amount": 1.5},
+            {"type": "deposit", "client": 1, "tx": 2, "amount": "1.00001"},
+            {"type": "dispute", "client": 1, "tx": 1}
+        ]"#;
+        let json_lines = "{\"type\": \"deposit\", \"client\": 1, \"tx\": 1, \"amount\": \"1.5\"}\n\
+                          {\"type\": \"deposit\", \"client\": 1, \"tx\": 2, \"amount\": 1.00001}\n\
+                          \n\
+                          {\"type\": \"dispute\", \"client\": 1, \"tx\": 1, \"amount\": null}\n";
+
+        let expected = vec![Ok(Some(15000)), Err(Some(2)), Ok(None)];
+        assert_eq!(amounts(csv, Format::Csv), expected);
+        assert_eq!(amounts(json, Format::Json), expected);
+        assert_eq!(amounts(json_lines, Format::JsonLines), expected);
+    }
+
+    #[test]
+    fn test_invalid_rows_recover_ids() {
+        let csv = "type,client,tx,amount\ndeposit,3,17,1.23456\nbogus\n";
+        let rejections = read(csv.as_bytes(), Format::Csv)
+            .unwrap()
+            .map(|row| row.unwrap_err())
+            .collect::<Vec<_>>();
+        assert_eq!(rejections[0].line, Some(2));
+        assert_eq!(rejections[0].tx, Some(17));
+        assert_eq!(rejections[0].client, Some(3));
+        assert_eq!(rejections[0].raw, "deposit,3,17,1.23456");
+        assert_eq!(rejections[1].line, Some(3));
+        assert_eq!(rejections[1].tx, None);
+        assert_eq!(rejections[1].raw, "bogus");
+
+        let json_lines =
+            "{\"type\": \"deposit\", \"client\": 3, \"tx\": 17, \"amount\": 1.23456}\n";
+        let rejection = read(json_lines.as_bytes(), Format::JsonLines)
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap_err();
+        assert_eq!(rejection.line, Some(1));
+        assert_eq!(rejection.tx, Some(17));
+        assert_eq!(rejection.client, Some(3));
+    }
+
+    #[test]
+    fn test_format_from_path() {
+        assert_eq!(Format::from_path("in.csv"), Format::Csv);
+        assert_eq!(Format::from_path("in.json"), Format::Json);
+        assert_eq!(Format::from_path("in.ndjson"), Format::JsonLines);
+        assert_eq!(Format::from_path("in"), Format::Csv);
+    }
+}
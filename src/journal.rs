@@ -0,0 +1,325 @@
+//! A record of every change made to an account, in the order it was made.
+//!
+//! Each applied transaction adds one [`Entry`] to the state's journal, holding the account's
+//! balances in the transaction's currency from just before and just after it. A conversion or a
+//! transfer, or the chargeback of one, changes two balances and adds an entry for each, and a
+//! transfer to a client without an account opens one. Journals are written as JSON lines, one
+//! entry per line, with amounts as raw ten-thousandths like a
+//! [`Snapshot`](crate::snapshot::Snapshot), and [`replay`] rebuilds the accounts and stored
+//! transactions from one.
+
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, Write};
+use std::mem;
+use thiserror::Error;
+
+use crate::{
+    currency::Currency,
+    money::{units, Money},
+    process::State,
+    store::{AccountStore, StoreError, TransactionStore},
+    transaction::TransactionStatus::*,
+    Account, Balance, Converted, StoredTransaction, TransactionType,
+};
+
+/// What was done to an account's balances.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Operation {
+    /// The account was created, with nothing in it.
+    Open,
+    Deposit,
+    Withdraw,
+    /// Funds moved from available to held.
+    Hold,
+    /// Funds moved from held to available.
+    Release,
+    /// Held funds were reversed and the account locked.
+    Chargeback,
+    /// A transaction's dispute status changed without moving any funds.
+    Mark,
+    /// An administrator locked the account.
+    Lock,
+    /// An administrator unlocked the account.
+    Unlock,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Balances {
+    #[serde(with = "units")]
+    pub available: Money,
+    #[serde(with = "units")]
+    pub held: Money,
+    #[serde(with = "units")]
+    pub total: Money,
+    pub locked: bool,
+}
+
+impl Balances {
+    /// The account's balances in `currency`.
+    pub fn of(account: &Account, currency: Currency) -> Self {
+        let balance = account.balance(currency);
+        Balances {
+            available: balance.available,
+            held: balance.held,
+            total: balance.total,
+            locked: account.locked,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Entry {
+    pub tx: u32,
+    pub client: u16,
+    /// The transaction that made the change.
+    pub r#type: TransactionType,
+    pub operation: Operation,
+    /// The amount moved by the operation, zero when opening an account.
+    #[serde(with = "units")]
+    pub amount: Money,
+    /// The currency of the balances the operation changed.
+    #[serde(default, skip_serializing_if = "Currency::is_default")]
+    pub currency: Currency,
+    pub before: Balances,
+    pub after: Balances,
+    /// Why an administrator locked or unlocked the account.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+/// Collects journal entries, optionally writing each one out as it arrives. Without a sink every
+/// entry is kept in memory.
+pub struct Journal {
+    sink: Option<Box<dyn Write + Send>>,
+    // every entry without a sink, otherwise the ones held back by an open batch
+    entries: Vec<Entry>,
+    batch_start: Option<usize>,
+}
+
+impl Journal {
+    pub fn new() -> Self {
+        Journal {
+            sink: None,
+            entries: Vec::new(),
+            batch_start: None,
+        }
+    }
+
+    /// Writes entries to `sink` as JSON lines. Entries are written one at a time, so `sink` should
+    /// be buffered.
+    pub fn with_sink(sink: Box<dyn Write + Send>) -> Self {
+        Journal {
+            sink: Some(sink),
+            entries: Vec::new(),
+            batch_start: None,
+        }
+    }
+
+    /// Entries kept in memory, which is all of them for a journal without a sink.
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    pub fn record(&mut self, entry: Entry) -> Result<(), StoreError> {
+        self.entries.push(entry);
+        if self.batch_start.is_none() {
+            self.write_entries()?;
+        }
+
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<(), StoreError> {
+        self.write_entries()?;
+        if let Some(sink) = self.sink.as_mut() {
+            sink.flush()?;
+        }
+
+        Ok(())
+    }
+
+    // entries from here on are held back until the batch ends
+    pub(crate) fn begin_batch(&mut self) {
+        self.batch_start = Some(self.entries.len());
+    }
+
+    // held back entries go out with the next one, or on flush
+    pub(crate) fn end_batch(&mut self) {
+        self.batch_start = None;
+    }
+
+    pub(crate) fn discard_batch(&mut self) {
+        if let Some(start) = self.batch_start.take() {
+            self.entries.truncate(start);
+        }
+    }
+
+    fn write_entries(&mut self) -> Result<(), StoreError> {
+        let sink = match self.sink.as_mut() {
+            Some(sink) => sink,
+            None => return Ok(()),
+        };
+
+        for entry in mem::take(&mut self.entries) {
+            serde_json::to_writer(&mut *sink, &entry).map_err(|e| StoreError::Io(e.to_string()))?;
+            sink.write_all(b"\n")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for Journal {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum JournalError {
+    #[error("could not read journal line {line}: {source}")]
+    Json {
+        line: usize,
+        source: serde_json::Error,
+    },
+    #[error("could not read journal: {0}")]
+    Io(#[from] std::io::Error),
+    #[error(
+        "journal entry for transaction {tx} expects client {client} to have different balances"
+    )]
+    Diverged { tx: u32, client: u16 },
+    #[error("journal entry for transaction {0} refers to a transaction the journal doesn't have")]
+    UnknownTransaction(u32),
+    #[error("store error: {0}")]
+    Store(#[from] StoreError),
+}
+
+/// Reads a journal and applies every entry in it to `state`, which should be in the state the
+/// journal started from, usually empty. Each entry's balances from before it are checked against
+/// the account, so a journal that doesn't fit the state is caught rather than half applied
+/// silently.
+pub fn replay<R, T, A>(r: R, state: &mut State<T, A>) -> Result<(), JournalError>
+where
+    R: BufRead,
+    T: TransactionStore,
+    A: AccountStore,
+{
+    for entry in read(r) {
+        apply(state, &entry?)?;
+    }
+
+    Ok(())
+}
+
+/// Reads the entries of a journal, in the order they were recorded.
+pub fn read<R: BufRead>(r: R) -> impl Iterator<Item = Result<Entry, JournalError>> {
+    r.lines().enumerate().filter_map(|(i, line)| {
+        let line = match line {
+            Ok(line) => line,
+            Err(e) => return Some(Err(e.into())),
+        };
+        if line.trim().is_empty() {
+            return None;
+        }
+
+        Some(
+            serde_json::from_str::<Entry>(&line).map_err(|source| JournalError::Json {
+                line: i + 1,
+                source,
+            }),
+        )
+    })
+}
+
+/// Applies a single journal entry to `state`.
+pub fn apply<T: TransactionStore, A: AccountStore>(
+    state: &mut State<T, A>,
+    entry: &Entry,
+) -> Result<(), JournalError> {
+    let diverged = JournalError::Diverged {
+        tx: entry.tx,
+        client: entry.client,
+    };
+
+    let mut account = match (state.accounts.get(entry.client)?, entry.operation) {
+        (None, Operation::Open) => Account::new(entry.client),
+        (Some(account), operation)
+            if operation != Operation::Open
+                && Balances::of(&account, entry.currency) == entry.before =>
+        {
+            account
+        }
+        _ => return Err(diverged),
+    };
+
+    let status = match entry.r#type {
+        _ if entry.operation == Operation::Open => None,
+        // the second entry of a conversion is what it credited
+        TransactionType::Convert if entry.operation == Operation::Deposit => {
+            let mut t = state
+                .transactions
+                .get(entry.tx)?
+                .ok_or(JournalError::UnknownTransaction(entry.tx))?;
+            t.converted = Some(Converted {
+                amount: entry.amount,
+                currency: entry.currency,
+            });
+            state.transactions.put(entry.tx, t)?;
+            None
+        }
+        // and the second entry of a transfer is the client it credited
+        TransactionType::Transfer if entry.operation == Operation::Deposit => {
+            let mut t = state
+                .transactions
+                .get(entry.tx)?
+                .ok_or(JournalError::UnknownTransaction(entry.tx))?;
+            t.to_client = Some(entry.client);
+            state.transactions.put(entry.tx, t)?;
+            None
+        }
+        TransactionType::Deposit
+        | TransactionType::Withdrawal
+        | TransactionType::Convert
+        | TransactionType::Transfer => {
+            state.transactions.put(
+                entry.tx,
+                StoredTransaction {
+                    r#type: entry.r#type,
+                    client: entry.client,
+                    amount: entry.amount,
+                    currency: entry.currency,
+                    converted: None,
+                    to_client: None,
+                    status: Processed,
+                },
+            )?;
+            None
+        }
+        TransactionType::Lock | TransactionType::Unlock => None,
+        TransactionType::Dispute => Some(Disputed),
+        TransactionType::Resolve => Some(Resolved),
+        TransactionType::Chargeback => Some(ChargedBack),
+    };
+    if let Some(status) = status {
+        let mut t = state
+            .transactions
+            .get(entry.tx)?
+            .ok_or(JournalError::UnknownTransaction(entry.tx))?;
+        t.status = status;
+        state.transactions.put(entry.tx, t)?;
+    }
+
+    account.set_balance(
+        entry.currency,
+        Balance {
+            available: entry.after.available,
+            held: entry.after.held,
+            total: entry.after.total,
+        },
+    );
+    account.locked = entry.after.locked;
+    state.accounts.put(account)?;
+
+    Ok(())
+}
@@ -1,7 +1,25 @@
 pub mod account;
+pub mod audit;
+pub mod batch;
+pub mod currency;
+pub mod input;
+pub mod journal;
+pub mod money;
+pub mod output;
+pub mod parallel;
 pub mod process;
+pub mod rates;
+pub mod reject;
+pub mod server;
+pub mod snapshot;
+pub mod statement;
+pub mod store;
 mod transaction;
 
-pub use account::Account;
+pub use account::{Account, Balance};
+pub use currency::Currency;
+pub use money::Money;
 pub use process::State;
-pub use transaction::{Transaction, TransactionType};
+pub use transaction::{
+    Converted, StoredTransaction, Transaction, TransactionError, TransactionStatus, TransactionType,
+};
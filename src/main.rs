@@ -1,37 +1,921 @@
-use std::env;
-use std::fs::File;
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::slice;
+use std::sync::{Arc, Mutex};
 
-use transactions::process::{self, State};
-use transactions::Transaction;
+use clap::{Args, CommandFactory, Parser, Subcommand, ValueEnum};
+use thiserror::Error;
+use tokio::net::TcpListener;
 
-fn main() {
-    // open the input file
-    let mut args = env::args();
-    args.next().expect("first arg is executable name");
-    let filename = args.next().expect("no filename provided");
-    let f = File::open(filename).expect("could not open file");
-    // TODO: do I need to buffer?
+use transactions::audit::{self, AuditError};
+use transactions::batch::Batch;
+use transactions::input::{self, InputError, Rows};
+use transactions::journal::{self, Journal, JournalError};
+use transactions::output::{self, OutputError};
+use transactions::process::{
+    self, LockedAccounts, Policy, State, TransactionProcessingError, WithdrawalDisputes,
+};
+use transactions::rates::{FileRates, RatesError, SharedRates};
+use transactions::reject::{Rejection, RejectionReport};
+use transactions::snapshot::{Snapshot, SnapshotError};
+use transactions::store::{
+    AccountStore, FileAccountStore, FileTransactionStore, StoreError, TransactionStore,
+};
+use transactions::{parallel, server, statement, Transaction};
 
-    // set up state
-    let mut state = State::new();
+// exit status for a run that rejected rows it wasn't allowed to
+const EXIT_REJECTED: u8 = 3;
+// exit status for arguments clap can't tell are wrong on its own, matching clap's
+const EXIT_USAGE: u8 = 2;
 
-    // process all transactions
-    let mut rdr = csv::Reader::from_reader(f);
-    for result in rdr.deserialize() {
-        if result.is_err() {
-            /* handle the error as desired */
-            continue;
+/// Applies deposits, withdrawals, conversions, transfers, disputes, resolves and chargebacks to
+/// client accounts and writes out the resulting balances.
+///
+/// `transactions <INPUT> [OPTIONS]` is short for `transactions process <INPUT> [OPTIONS]`.
+#[derive(Parser)]
+#[command(
+    version,
+    after_help = "Exit status is 0 on success, 1 on a fatal error, 2 on a usage error and 3 when \
+                  rows were rejected by `validate` or under `--bad-rows fail` or `--bad-rows abort`."
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Process transactions and write the resulting balances
+    Process(ProcessArgs),
+    /// Check that every row can be read and applied to an empty state, without writing balances
+    Validate(ValidateArgs),
+    /// Apply transactions on top of a snapshot or state directory and write the resulting balances
+    Replay(ReplayArgs),
+    /// Write the balances in a snapshot or state directory without processing anything
+    Report(ReportArgs),
+    /// List every transaction applied to each account, with the balances after each one
+    Statement(StatementArgs),
+    /// Accept transactions over TCP from any number of connections
+    Serve(ServeArgs),
+}
+
+#[derive(Args)]
+struct ProcessArgs {
+    /// Input file, or `-` for stdin
+    input: String,
+    #[command(flatten)]
+    format: FormatArgs,
+    /// Keep stored transactions in this file instead of in memory
+    #[arg(long, value_name = "PATH", conflicts_with = "state_dir")]
+    tx_store: Option<PathBuf>,
+    /// Resume from, and persist to, the accounts and transactions in this directory
+    #[arg(long, value_name = "DIR")]
+    state_dir: Option<PathBuf>,
+    /// Load a snapshot before processing
+    #[arg(long, value_name = "PATH")]
+    from_snapshot: Option<PathBuf>,
+    /// Write a snapshot of the state after processing
+    #[arg(long, value_name = "PATH")]
+    snapshot: Option<PathBuf>,
+    /// Append every change made to an account to this journal
+    #[arg(long, value_name = "PATH")]
+    journal: Option<PathBuf>,
+    /// Process on this many threads, sharding clients across them; state is kept in memory
+    #[arg(
+        long,
+        value_name = "N",
+        conflicts_with_all = ["tx_store", "state_dir", "from_snapshot", "journal"]
+    )]
+    workers: Option<NonZeroUsize>,
+    #[command(flatten)]
+    policy: PolicyArgs,
+    #[command(flatten)]
+    rejects: RejectArgs,
+    #[command(flatten)]
+    output: OutputArgs,
+}
+
+#[derive(Args)]
+struct ValidateArgs {
+    /// Input files, or `-` for stdin, each checked on its own
+    #[arg(required = true)]
+    inputs: Vec<String>,
+    #[command(flatten)]
+    format: FormatArgs,
+    /// Write every rejected row to this CSV file
+    #[arg(long, value_name = "PATH")]
+    rejects: Option<PathBuf>,
+    #[command(flatten)]
+    policy: PolicyArgs,
+}
+
+#[derive(Args)]
+struct ReplayArgs {
+    /// Input files, or `-` for stdin, applied in order
+    #[arg(required = true)]
+    inputs: Vec<String>,
+    #[command(flatten)]
+    format: FormatArgs,
+    #[command(flatten)]
+    source: SourceArgs,
+    /// Write a snapshot of the state after replaying
+    #[arg(long, value_name = "PATH")]
+    snapshot: Option<PathBuf>,
+    /// Append every change made to an account to this journal
+    #[arg(long, value_name = "PATH")]
+    journal: Option<PathBuf>,
+    #[command(flatten)]
+    policy: PolicyArgs,
+    #[command(flatten)]
+    rejects: RejectArgs,
+    #[command(flatten)]
+    output: OutputArgs,
+}
+
+#[derive(Args)]
+struct ReportArgs {
+    #[command(flatten)]
+    source: SourceArgs,
+    #[command(flatten)]
+    output: OutputArgs,
+}
+
+#[derive(Args)]
+struct StatementArgs {
+    /// Input files, or `-` for stdin, applied in order after the journal
+    #[arg(required_unless_present = "from_journal")]
+    inputs: Vec<String>,
+    #[command(flatten)]
+    format: FormatArgs,
+    /// Start from the changes recorded in this journal
+    #[arg(long, value_name = "PATH")]
+    from_journal: Option<PathBuf>,
+    /// Only list this client's account; may be given more than once
+    #[arg(long = "client", value_name = "CLIENT")]
+    clients: Vec<u16>,
+    #[command(flatten)]
+    policy: PolicyArgs,
+    #[command(flatten)]
+    rejects: RejectArgs,
+    /// Write the statement to this file instead of stdout
+    #[arg(long, value_name = "PATH")]
+    output: Option<PathBuf>,
+    /// Statement format: csv, json, ndjson or table
+    #[arg(long, value_name = "FORMAT", default_value = "csv")]
+    output_format: output::Format,
+}
+
+#[derive(Args)]
+struct ServeArgs {
+    /// Address to listen on, e.g. 127.0.0.1:7000
+    addr: String,
+    #[command(flatten)]
+    policy: PolicyArgs,
+}
+
+#[derive(Args)]
+struct FormatArgs {
+    /// Input format: csv, json or ndjson [default: guessed from the file extension, else csv]
+    #[arg(long, value_name = "FORMAT")]
+    format: Option<input::Format>,
+}
+
+#[derive(Args)]
+#[group(required = true, multiple = false)]
+struct SourceArgs {
+    /// Start from this snapshot
+    #[arg(long, value_name = "PATH")]
+    from_snapshot: Option<PathBuf>,
+    /// Start from the state rebuilt from this journal
+    #[arg(long, value_name = "PATH")]
+    from_journal: Option<PathBuf>,
+    /// Start from the accounts and transactions in this directory, which are updated in place
+    #[arg(long, value_name = "DIR")]
+    state_dir: Option<PathBuf>,
+}
+
+#[derive(Args)]
+struct PolicyArgs {
+    /// Don't allow a resolved transaction to be disputed again
+    #[arg(long)]
+    no_redispute: bool,
+    /// What disputing a withdrawal does: disallow, credit-on-chargeback or release
+    #[arg(long, value_name = "POLICY", default_value = "release")]
+    withdrawal_disputes: WithdrawalDisputes,
+    /// Check the engine's invariants after every transaction, stopping at the first one broken
+    #[arg(long)]
+    audit: bool,
+    /// Convert currencies at the rates in this CSV of timestamp, from, to and rate
+    #[arg(long, value_name = "PATH")]
+    rates: Option<PathBuf>,
+    /// Accept lock and unlock transactions, which should only come from administrators
+    #[arg(long)]
+    admin: bool,
+    /// What a locked account still accepts: none, or transaction types such as resolve,chargeback
+    #[arg(long, value_name = "TYPES", default_value = "none")]
+    locked_accounts: LockedAccounts,
+}
+
+impl PolicyArgs {
+    fn policy(&self) -> Policy {
+        Policy {
+            allow_redispute: !self.no_redispute,
+            withdrawal_disputes: self.withdrawal_disputes,
+            audit: self.audit,
+            admin: self.admin,
+            locked_accounts: self.locked_accounts,
         }
+    }
 
-        let t: Transaction = result.expect("could not get transaction");
-        if process::process_one(&mut state, t).is_err() { /* handle the error as desired */ }
+    fn rates(&self) -> Result<Option<SharedRates>, Error> {
+        match self.rates.as_deref() {
+            Some(path) => {
+                let rates = FileRates::open(path).map_err(|source| Error::Rates {
+                    path: path.to_path_buf(),
+                    source,
+                })?;
+                Ok(Some(Arc::new(rates)))
+            }
+            None => Ok(None),
+        }
     }
+}
+
+#[derive(Args)]
+struct RejectArgs {
+    /// Write every rejected row to this CSV file, and a count per error kind to stderr
+    #[arg(long, value_name = "PATH")]
+    rejects: Option<PathBuf>,
+    /// What to do about rows that can't be read or applied
+    #[arg(long, value_enum, default_value_t = BadRows::Skip)]
+    bad_rows: BadRows,
+    /// Same as `--bad-rows abort`
+    #[arg(long, conflicts_with = "bad_rows")]
+    strict: bool,
+}
+
+impl RejectArgs {
+    fn bad_rows(&self) -> BadRows {
+        if self.strict {
+            BadRows::Abort
+        } else {
+            self.bad_rows
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum BadRows {
+    /// Leave them out of the balances
+    Skip,
+    /// Leave them out of the balances and describe each one on stderr
+    Warn,
+    /// Like warn, but exit with status 3 once the balances are written
+    Fail,
+    /// Stop at the first one and exit with status 3 without writing balances or a snapshot
+    Abort,
+}
+
+#[derive(Args)]
+struct OutputArgs {
+    /// Write balances to this file instead of stdout
+    #[arg(long, value_name = "PATH")]
+    output: Option<PathBuf>,
+    /// Balance format: csv, json, ndjson or table
+    #[arg(long, value_name = "FORMAT", default_value = "csv")]
+    output_format: output::Format,
+    /// Order balances by client, available, held or total
+    #[arg(long, value_name = "KEY", default_value = "client")]
+    sort_by: output::SortKey,
+}
+
+impl OutputArgs {
+    fn write<A: AccountStore>(&self, accounts: &A) -> Result<(), Error> {
+        let mut rows = output::rows(accounts.all());
+        output::sort_rows(&mut rows, self.sort_by);
+        output::write_rows(
+            output_writer(self.output.as_deref())?,
+            &rows,
+            self.output_format,
+        )?;
+
+        Ok(())
+    }
+}
+
+fn output_writer(path: Option<&Path>) -> Result<BufWriter<Box<dyn Write>>, Error> {
+    let w: Box<dyn Write> = match path.filter(|path| *path != Path::new("-")) {
+        Some(path) => Box::new(create(path)?),
+        None => Box::new(io::stdout()),
+    };
+
+    Ok(BufWriter::new(w))
+}
+
+#[derive(Debug, Error)]
+enum Error {
+    #[error("{}: {source}", path.display())]
+    Io { path: PathBuf, source: io::Error },
+    #[error("{path}: {source}")]
+    Input { path: String, source: InputError },
+    #[error(transparent)]
+    Output(#[from] OutputError),
+    #[error(transparent)]
+    Snapshot(#[from] SnapshotError),
+    #[error(transparent)]
+    Journal(#[from] JournalError),
+    #[error("{}: {source}", path.display())]
+    Rates { path: PathBuf, source: RatesError },
+    #[error("state store: {0}")]
+    Store(#[from] StoreError),
+    #[error("could not write rejections: {0}")]
+    Rejects(#[from] csv::Error),
+    #[error("server failed: {0}")]
+    Serve(io::Error),
+    #[error("{0}")]
+    Usage(String),
+    #[error("aborted at {location}: {}: {}\n  record: {}", .rejection.error, .rejection.message, .rejection.raw)]
+    Aborted {
+        location: String,
+        rejection: Rejection,
+    },
+    #[error("audit failed at {location}: {}\n  record: {}", .rejection.message, .rejection.raw)]
+    Audit {
+        location: String,
+        rejection: Rejection,
+    },
+    #[error("audit of the loaded state failed: {0}")]
+    AuditState(#[from] AuditError),
+}
+
+impl Error {
+    fn exit_code(&self) -> ExitCode {
+        match self {
+            Error::Usage(_) => ExitCode::from(EXIT_USAGE),
+            Error::Aborted { .. } => ExitCode::from(EXIT_REJECTED),
+            _ => ExitCode::FAILURE,
+        }
+    }
+}
+
+fn open(path: &Path) -> Result<File, Error> {
+    File::open(path).map_err(|source| Error::Io {
+        path: path.to_path_buf(),
+        source,
+    })
+}
+
+fn create(path: &Path) -> Result<File, Error> {
+    File::create(path).map_err(|source| Error::Io {
+        path: path.to_path_buf(),
+        source,
+    })
+}
+
+// how an input is referred to in diagnostics
+fn source_name(path: &str) -> &str {
+    if path == "-" {
+        "<stdin>"
+    } else {
+        path
+    }
+}
+
+fn read_input(path: &str, format: Option<input::Format>) -> Result<Rows, Error> {
+    let format = format.unwrap_or_else(|| input::Format::from_path(path));
+    let reader: Box<dyn Read> = if path == "-" {
+        Box::new(io::stdin())
+    } else {
+        Box::new(open(Path::new(path))?)
+    };
+
+    input::read(BufReader::new(reader), format).map_err(|source| Error::Input {
+        path: source_name(path).to_string(),
+        source,
+    })
+}
+
+// where a rejected row is, for diagnostics
+fn location(source: &str, rejection: &Rejection) -> String {
+    match rejection.line {
+        Some(line) => format!("{}:{}", source_name(source), line),
+        None => match rejection.tx {
+            Some(tx) => format!("{} (tx {})", source_name(source), tx),
+            None => source_name(source).to_string(),
+        },
+    }
+}
+
+// collects rejections, describing each on stderr as well if asked to
+struct Rejects {
+    report: RejectionReport,
+    path: Option<PathBuf>,
+    bad_rows: BadRows,
+    summary: bool,
+}
+
+impl Rejects {
+    fn new(path: Option<&Path>, bad_rows: BadRows) -> Result<Self, Error> {
+        let report = match path {
+            Some(path) => RejectionReport::with_sink(Box::new(create(path)?)),
+            None => RejectionReport::new(),
+        };
+
+        Ok(Rejects {
+            report,
+            path: path.map(Path::to_path_buf),
+            bad_rows,
+            summary: path.is_some(),
+        })
+    }
+
+    fn record(&mut self, source: &str, rejection: Rejection) -> Result<(), Error> {
+        let location = location(source, &rejection);
+        match self.bad_rows {
+            BadRows::Skip => {}
+            BadRows::Warn | BadRows::Fail => {
+                eprintln!("{}: {}: {}", location, rejection.error, rejection.message)
+            }
+            BadRows::Abort => {
+                // the rejection still goes to the rejects file before giving up
+                self.report.record(rejection.clone())?;
+                self.flush()?;
+                return Err(Error::Aborted {
+                    location,
+                    rejection,
+                });
+            }
+        }
+
+        self.report.record(rejection)?;
+
+        Ok(())
+    }
+
+    // the row was applied, and broke the state, so there is no carrying on whatever is done about
+    // bad rows
+    fn audit_failed(&mut self, source: &str, rejection: Rejection) -> Error {
+        if let Err(e) = self.flush() {
+            return e;
+        }
+
+        Error::Audit {
+            location: location(source, &rejection),
+            rejection,
+        }
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        self.report.flush().map_err(|source| Error::Io {
+            path: self.path.clone().unwrap_or_default(),
+            source,
+        })
+    }
+
+    // flushes the rejections and works out the exit status
+    fn finish(mut self) -> Result<ExitCode, Error> {
+        self.flush()?;
+
+        // summarize rejections on stderr so they don't get mixed in with the output
+        if self.summary {
+            // nothing useful can be done if stderr is gone
+            let _ = self.report.write_summary(io::stderr());
+        }
+
+        if self.bad_rows == BadRows::Fail && self.report.total() > 0 {
+            Ok(ExitCode::from(EXIT_REJECTED))
+        } else {
+            Ok(ExitCode::SUCCESS)
+        }
+    }
+}
 
-    // print output to stdout
-    // TODO: do I need to lock this?
-    let mut wtr = csv::Writer::from_writer(std::io::stdout());
-    for (_, account) in state.accounts.iter() {
-        wtr.serialize(account).expect("could not write record");
+fn process_rows<F>(
+    source: &str,
+    rows: Rows,
+    rejects: &mut Rejects,
+    apply: &mut F,
+) -> Result<(), Error>
+where
+    F: FnMut(Transaction) -> Result<(), TransactionProcessingError>,
+{
+    for row in rows {
+        let rejection = match row {
+            Err(rejection) => rejection,
+            Ok((record, t)) => {
+                let (tx, client) = (t.tx, t.client);
+                match apply(t) {
+                    Ok(()) => continue,
+                    Err(e @ TransactionProcessingError::InvariantViolated { .. }) => {
+                        let rejection = Rejection::failed(&record, tx, client, &e);
+                        return Err(rejects.audit_failed(source, rejection));
+                    }
+                    Err(e) => Rejection::failed(&record, tx, client, &e),
+                }
+            }
+        };
+
+        rejects.record(source, rejection)?;
+    }
+
+    Ok(())
+}
+
+fn process_rows_parallel(
+    source: &str,
+    rows: Rows,
+    workers: usize,
+    policy: Policy,
+    rates: Option<SharedRates>,
+    rejects: &mut Rejects,
+) -> Result<State, Error> {
+    // each rejection, and whether it was an audit failure
+    let mut rejections = Vec::new();
+    let transactions = rows.filter_map(|row| match row {
+        Err(rejection) => {
+            rejections.push((rejection, false));
+            None
+        }
+        Ok((record, t)) => Some(((record, t.tx, t.client), t)),
+    });
+
+    let (state, errors) = parallel::process_parallel(transactions, workers, policy, rates);
+    rejections.extend(errors.into_iter().map(|((record, tx, client), e)| {
+        let audit = matches!(e, TransactionProcessingError::InvariantViolated { .. });
+        (Rejection::failed(&record, tx, client, &e), audit)
+    }));
+
+    // workers finish in any order, put rejections back in input order
+    rejections.sort_by_key(|(rejection, _)| rejection.line);
+    for (rejection, audit) in rejections {
+        if audit {
+            return Err(rejects.audit_failed(source, rejection));
+        }
+        rejects.record(source, rejection)?;
+    }
+
+    Ok(state)
+}
+
+fn write_snapshot<T: TransactionStore, A: AccountStore>(
+    state: &State<T, A>,
+    path: &Path,
+) -> Result<(), Error> {
+    let f = create(path)?;
+    Snapshot::capture(state)?.write(BufWriter::new(f))?;
+
+    Ok(())
+}
+
+// the files a state directory keeps its stores in
+const TRANSACTIONS_FILE: &str = "transactions.db";
+const ACCOUNTS_FILE: &str = "accounts.log";
+
+fn open_state_dir(
+    dir: &Path,
+    policy: Policy,
+) -> Result<State<FileTransactionStore, FileAccountStore>, Error> {
+    fs::create_dir_all(dir).map_err(|source| Error::Io {
+        path: dir.to_path_buf(),
+        source,
+    })?;
+    let transactions = FileTransactionStore::open(dir.join(TRANSACTIONS_FILE))?;
+    let accounts = FileAccountStore::open(dir.join(ACCOUNTS_FILE))?;
+
+    Ok(State::with_stores(transactions, accounts, policy))
+}
+
+// opens a state directory for reading, without creating it or anything in it
+fn open_existing_state_dir(
+    dir: &Path,
+) -> Result<State<FileTransactionStore, FileAccountStore>, Error> {
+    let transactions = FileTransactionStore::open_existing(dir.join(TRANSACTIONS_FILE))?;
+    let accounts = FileAccountStore::open_existing(dir.join(ACCOUNTS_FILE))?;
+
+    Ok(State::with_stores(
+        transactions,
+        accounts,
+        Policy::default(),
+    ))
+}
+
+// what to do to a state: optionally restore a snapshot, apply every input in turn, and optionally
+// write a snapshot of the result
+struct Job<'a> {
+    from_snapshot: Option<&'a Path>,
+    from_journal: Option<&'a Path>,
+    inputs: &'a [String],
+    format: Option<input::Format>,
+    snapshot: Option<&'a Path>,
+    journal: Option<&'a Path>,
+    // exchange rates for conversions
+    rates: Option<SharedRates>,
+    // whether the inputs are applied as one batch, rolled back along with their journal entries
+    // if the run is aborted
+    atomic: bool,
+}
+
+impl Job<'_> {
+    fn run<T: TransactionStore, A: AccountStore>(
+        &self,
+        state: &mut State<T, A>,
+        rejects: &mut Rejects,
+    ) -> Result<(), Error> {
+        state.rates = self.rates.clone();
+        if let Some(path) = self.from_snapshot {
+            Snapshot::read(BufReader::new(open(path)?))?.restore(state)?;
+        }
+        if let Some(path) = self.from_journal {
+            journal::replay(BufReader::new(open(path)?), state)?;
+        }
+        if state.policy.audit {
+            audit::check(state)?;
+        }
+        if let Some(path) = self.journal {
+            let f = OpenOptions::new()
+                .append(true)
+                .create(true)
+                .open(path)
+                .map_err(|source| Error::Io {
+                    path: path.to_path_buf(),
+                    source,
+                })?;
+            state.journal = Some(Journal::with_sink(Box::new(BufWriter::new(f))));
+        }
+
+        if self.atomic {
+            let mut batch = Batch::new(state);
+            let result = self.process_inputs(rejects, &mut |t| batch.process(t));
+            match result {
+                Ok(()) => batch.commit()?,
+                Err(_) => batch.rollback()?,
+            }
+            result?;
+        } else {
+            self.process_inputs(rejects, &mut |t| process::process_one(state, t))?;
+        }
+        if let Some(journal) = state.journal.as_mut() {
+            journal.flush()?;
+        }
+
+        if let Some(path) = self.snapshot {
+            write_snapshot(state, path)?;
+        }
+
+        Ok(())
+    }
+
+    fn process_inputs<F>(&self, rejects: &mut Rejects, apply: &mut F) -> Result<(), Error>
+    where
+        F: FnMut(Transaction) -> Result<(), TransactionProcessingError>,
+    {
+        for path in self.inputs {
+            let rows = read_input(path, self.format)?;
+            process_rows(path, rows, rejects, apply)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn process(args: ProcessArgs) -> Result<ExitCode, Error> {
+    let policy = args.policy.policy();
+    let bad_rows = args.rejects.bad_rows();
+    // an aborted run leaves a state directory as it was, but restoring a snapshot into it can't
+    // be undone
+    if bad_rows == BadRows::Abort && args.state_dir.is_some() && args.from_snapshot.is_some() {
+        return Err(Error::Usage(
+            "--bad-rows abort can't restore a snapshot into a state directory".to_string(),
+        ));
+    }
+    let mut rejects = Rejects::new(args.rejects.rejects.as_deref(), bad_rows)?;
+    let job = Job {
+        from_snapshot: args.from_snapshot.as_deref(),
+        from_journal: None,
+        inputs: slice::from_ref(&args.input),
+        format: args.format.format,
+        snapshot: args.snapshot.as_deref(),
+        journal: args.journal.as_deref(),
+        rates: args.policy.rates()?,
+        // an aborted run leaves nothing behind in a state directory or journal
+        atomic: bad_rows == BadRows::Abort && (args.state_dir.is_some() || args.journal.is_some()),
+    };
+
+    if let Some(workers) = args.workers {
+        // each worker keeps its shard of the state in memory
+        let rows = read_input(&args.input, job.format)?;
+        let state = process_rows_parallel(
+            &args.input,
+            rows,
+            workers.get(),
+            policy,
+            job.rates.clone(),
+            &mut rejects,
+        )?;
+        if let Some(path) = job.snapshot {
+            write_snapshot(&state, path)?;
+        }
+        args.output.write(&state.accounts)?;
+    } else if let Some(dir) = args.state_dir.as_deref() {
+        let mut state = open_state_dir(dir, policy)?;
+        job.run(&mut state, &mut rejects)?;
+        state.accounts.compact()?;
+        args.output.write(&state.accounts)?;
+    } else if let Some(path) = args.tx_store.as_deref() {
+        let store = FileTransactionStore::create(path)?;
+        let mut state = State::with_store(store, policy);
+        job.run(&mut state, &mut rejects)?;
+        args.output.write(&state.accounts)?;
+    } else {
+        let mut state = State::with_policy(policy);
+        job.run(&mut state, &mut rejects)?;
+        args.output.write(&state.accounts)?;
+    }
+
+    rejects.finish()
+}
+
+fn validate(args: ValidateArgs) -> Result<ExitCode, Error> {
+    let mut rejects = Rejects::new(args.rejects.as_deref(), BadRows::Fail)?;
+    rejects.summary = true;
+
+    // every input is checked against a fresh state, as if it were processed on its own
+    let rates = args.policy.rates()?;
+    for input in args.inputs.iter() {
+        let job = Job {
+            from_snapshot: None,
+            from_journal: None,
+            inputs: slice::from_ref(input),
+            format: args.format.format,
+            snapshot: None,
+            journal: None,
+            rates: rates.clone(),
+            atomic: false,
+        };
+        job.run(&mut State::with_policy(args.policy.policy()), &mut rejects)?;
+    }
+
+    rejects.finish()
+}
+
+fn replay(args: ReplayArgs) -> Result<ExitCode, Error> {
+    let policy = args.policy.policy();
+    let bad_rows = args.rejects.bad_rows();
+    let mut rejects = Rejects::new(args.rejects.rejects.as_deref(), bad_rows)?;
+    let job = Job {
+        from_snapshot: args.source.from_snapshot.as_deref(),
+        from_journal: args.source.from_journal.as_deref(),
+        inputs: &args.inputs,
+        format: args.format.format,
+        snapshot: args.snapshot.as_deref(),
+        journal: args.journal.as_deref(),
+        rates: args.policy.rates()?,
+        atomic: bad_rows == BadRows::Abort
+            && (args.source.state_dir.is_some() || args.journal.is_some()),
+    };
+
+    match args.source.state_dir.as_deref() {
+        Some(dir) => {
+            let mut state = open_state_dir(dir, policy)?;
+            job.run(&mut state, &mut rejects)?;
+            state.accounts.compact()?;
+            args.output.write(&state.accounts)?;
+        }
+        None => {
+            let mut state = State::with_policy(policy);
+            job.run(&mut state, &mut rejects)?;
+            args.output.write(&state.accounts)?;
+        }
+    }
+
+    rejects.finish()
+}
+
+fn report(args: ReportArgs) -> Result<ExitCode, Error> {
+    match args.source.state_dir.as_deref() {
+        Some(dir) => {
+            let state = open_existing_state_dir(dir)?;
+            args.output.write(&state.accounts)?;
+        }
+        None => {
+            let job = Job {
+                from_snapshot: args.source.from_snapshot.as_deref(),
+                from_journal: args.source.from_journal.as_deref(),
+                inputs: &[],
+                format: None,
+                snapshot: None,
+                journal: None,
+                rates: None,
+                atomic: false,
+            };
+            let mut state = State::new();
+            job.run(&mut state, &mut Rejects::new(None, BadRows::Skip)?)?;
+            args.output.write(&state.accounts)?;
+        }
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+fn statement(args: StatementArgs) -> Result<ExitCode, Error> {
+    let mut state = State::with_policy(args.policy.policy());
+    let mut entries = Vec::new();
+    if let Some(path) = args.from_journal.as_deref() {
+        for entry in journal::read(BufReader::new(open(path)?)) {
+            let entry = entry?;
+            journal::apply(&mut state, &entry)?;
+            entries.push(entry);
+        }
+    }
+
+    state.journal = Some(Journal::new());
+    let mut rejects = Rejects::new(args.rejects.rejects.as_deref(), args.rejects.bad_rows())?;
+    let job = Job {
+        from_snapshot: None,
+        from_journal: None,
+        inputs: &args.inputs,
+        format: args.format.format,
+        snapshot: None,
+        journal: None,
+        rates: args.policy.rates()?,
+        atomic: false,
+    };
+    job.run(&mut state, &mut rejects)?;
+    entries.extend(
+        state
+            .journal
+            .iter()
+            .flat_map(|j| j.entries().iter().cloned()),
+    );
+
+    let lines = statement::statements(entries)
+        .into_iter()
+        .filter(|l| args.clients.is_empty() || args.clients.contains(&l.client))
+        .collect::<Vec<_>>();
+    statement::write_statement(
+        output_writer(args.output.as_deref())?,
+        &lines,
+        args.output_format,
+    )?;
+
+    rejects.finish()
+}
+
+fn serve(args: ServeArgs) -> Result<ExitCode, Error> {
+    let mut state = State::with_policy(args.policy.policy());
+    state.rates = args.policy.rates()?;
+    let state = Arc::new(Mutex::new(state));
+    let runtime = tokio::runtime::Runtime::new().map_err(Error::Serve)?;
+    runtime
+        .block_on(async {
+            let listener = TcpListener::bind(&args.addr).await?;
+            server::serve(listener, state).await
+        })
+        .map_err(Error::Serve)?;
+
+    Ok(ExitCode::SUCCESS)
+}
+
+// `transactions <INPUT>` predates the subcommands, so when the first argument isn't a subcommand
+// or a top-level flag the arguments are taken to be for `process`
+fn with_default_command<I: IntoIterator<Item = OsString>>(args: I) -> Vec<OsString> {
+    let mut args = args.into_iter().collect::<Vec<_>>();
+    let explicit = match args.get(1).and_then(|arg| arg.to_str()) {
+        None => true,
+        Some(arg) => {
+            ["help", "-h", "--help", "-V", "--version"].contains(&arg)
+                || Cli::command()
+                    .get_subcommands()
+                    .any(|c| c.get_name() == arg)
+        }
+    };
+    if !explicit {
+        args.insert(1, "process".into());
+    }
+
+    args
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse_from(with_default_command(std::env::args_os()));
+    let result = match cli.command {
+        Command::Process(args) => process(args),
+        Command::Validate(args) => validate(args),
+        Command::Replay(args) => replay(args),
+        Command::Report(args) => report(args),
+        Command::Statement(args) => statement(args),
+        Command::Serve(args) => serve(args),
+    };
+
+    match result {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            e.exit_code()
+        }
     }
-    wtr.flush().expect("could not flush");
 }
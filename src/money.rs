@@ -0,0 +1,377 @@
+//! Fixed-point decimal amounts.
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+use thiserror::Error;
+
+/// A decimal amount kept as a whole number of `10^-SCALE` units, so that adding and subtracting
+/// is exact. Most code wants [`Money`], which has the four decimal places used throughout the
+/// crate.
+///
+/// Amounts are written and parsed as decimals such as `-12.5000`, and serialize as those strings.
+/// Use [`units`] to serialize the raw number of units instead. `SCALE` can be at most 18.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct FixedPoint<const SCALE: u32>(i64);
+
+/// An amount with four decimal places, the precision of every input and balance.
+pub type Money = FixedPoint<4>;
+
+#[derive(Debug, PartialEq, Error)]
+pub enum ParseMoneyError {
+    #[error("amount improperly formatted")]
+    Malformed,
+    #[error("amount has more than {0} decimal places")]
+    TooPrecise(u32),
+    #[error("amount too large")]
+    TooLarge,
+}
+
+impl<const SCALE: u32> FixedPoint<SCALE> {
+    pub const ZERO: Self = FixedPoint(0);
+    pub const MAX: Self = FixedPoint(i64::MAX);
+    /// The number of units in a whole amount, `10^SCALE`.
+    pub const UNITS_PER_WHOLE: i64 = 10_i64.pow(SCALE);
+
+    pub const fn from_units(units: i64) -> Self {
+        FixedPoint(units)
+    }
+
+    pub const fn units(self) -> i64 {
+        self.0
+    }
+
+    pub fn is_negative(self) -> bool {
+        self.0 < 0
+    }
+
+    pub fn checked_add(self, other: Self) -> Option<Self> {
+        self.0.checked_add(other.0).map(FixedPoint)
+    }
+
+    pub fn checked_sub(self, other: Self) -> Option<Self> {
+        self.0.checked_sub(other.0).map(FixedPoint)
+    }
+
+    pub fn checked_neg(self) -> Option<Self> {
+        self.0.checked_neg().map(FixedPoint)
+    }
+
+    pub fn saturating_add(self, other: Self) -> Self {
+        FixedPoint(self.0.saturating_add(other.0))
+    }
+
+    pub fn saturating_sub(self, other: Self) -> Self {
+        FixedPoint(self.0.saturating_sub(other.0))
+    }
+
+    /// Multiplies by an amount of any scale, such as an exchange rate, keeping this amount's
+    /// scale. The exact product is truncated toward zero to fit.
+    pub fn checked_mul<const OTHER: u32>(self, other: FixedPoint<OTHER>) -> Option<Self> {
+        let product =
+            self.0 as i128 * other.0 as i128 / FixedPoint::<OTHER>::UNITS_PER_WHOLE as i128;
+        i64::try_from(product).ok().map(FixedPoint)
+    }
+}
+
+impl<const SCALE: u32> fmt::Display for FixedPoint<SCALE> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // unsigned, so that i64::MIN doesn't overflow
+        let magnitude = self.0.unsigned_abs();
+        let per_whole = Self::UNITS_PER_WHOLE as u64;
+        let sign = if self.0 < 0 { "-" } else { "" };
+        let whole = magnitude / per_whole;
+
+        if SCALE == 0 {
+            write!(f, "{}{}", sign, whole)
+        } else {
+            let fractional = magnitude % per_whole;
+            write!(
+                f,
+                "{}{}.{:0>width$}",
+                sign,
+                whole,
+                fractional,
+                width = SCALE as usize
+            )
+        }
+    }
+}
+
+impl<const SCALE: u32> FromStr for FixedPoint<SCALE> {
+    type Err = ParseMoneyError;
+
+    /// Parses a decimal with at most `SCALE` decimal places, such as `1`, `1.`, `-1.5` or `+1.25`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (negative, unsigned) = match s.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, s.strip_prefix('+').unwrap_or(s)),
+        };
+        let (whole, fractional) = match unsigned.split_once('.') {
+            Some((whole, fractional)) => (whole, fractional),
+            None => (unsigned, ""),
+        };
+
+        let digits = |s: &str| s.bytes().all(|b| b.is_ascii_digit());
+        if whole.is_empty() || !digits(whole) || !digits(fractional) {
+            return Err(ParseMoneyError::Malformed);
+        }
+        if fractional.len() > SCALE as usize {
+            return Err(ParseMoneyError::TooPrecise(SCALE));
+        }
+
+        // only digits are left, so failing to parse means the number is too long
+        let whole = whole
+            .parse::<i64>()
+            .map_err(|_| ParseMoneyError::TooLarge)?;
+        let fractional = match fractional {
+            "" => 0,
+            _ => format!("{:0<width$}", fractional, width = SCALE as usize)
+                .parse::<i64>()
+                .map_err(|_| ParseMoneyError::Malformed)?,
+        };
+
+        let units = whole
+            .checked_mul(Self::UNITS_PER_WHOLE)
+            .and_then(|units| units.checked_add(fractional))
+            .ok_or(ParseMoneyError::TooLarge)?;
+
+        Ok(FixedPoint(if negative { -units } else { units }))
+    }
+}
+
+impl<const SCALE: u32> Serialize for FixedPoint<SCALE> {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.collect_str(self)
+    }
+}
+
+impl<'de, const SCALE: u32> Deserialize<'de> for FixedPoint<SCALE> {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        let Text(text) = Text::deserialize(d)?;
+        text.parse().map_err(de::Error::custom)
+    }
+}
+
+// serde_json hands the raw text of any value to a newtype struct with this name, which is how
+// `serde_json::value::RawValue` works; other formats see an ordinary newtype struct
+const RAW_JSON: &str = "$serde_json::private::RawValue";
+
+/// The text of an amount as it was written: a CSV field, or a JSON string or number. Amounts are
+/// parsed from this rather than from whatever type the format would read them as, so that a
+/// number never goes through a float on the way in.
+pub(crate) struct Text(pub(crate) String);
+
+impl<'de> Deserialize<'de> for Text {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        struct Visitor;
+
+        impl<'de> de::Visitor<'de> for Visitor {
+            type Value = Text;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str("a decimal amount")
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+                Ok(Text(v.to_string()))
+            }
+
+            fn visit_i64<E: de::Error>(self, v: i64) -> Result<Self::Value, E> {
+                Ok(Text(v.to_string()))
+            }
+
+            fn visit_u64<E: de::Error>(self, v: u64) -> Result<Self::Value, E> {
+                Ok(Text(v.to_string()))
+            }
+
+            fn visit_newtype_struct<D: Deserializer<'de>>(
+                self,
+                d: D,
+            ) -> Result<Self::Value, D::Error> {
+                d.deserialize_str(self)
+            }
+
+            // the raw JSON, as a map from serde_json's marker to the text
+            fn visit_map<A: de::MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+                let raw = match map.next_entry::<de::IgnoredAny, String>()? {
+                    Some((_, raw)) => raw,
+                    None => return Err(de::Error::invalid_length(0, &self)),
+                };
+                if raw.starts_with('"') {
+                    serde_json::from_str(&raw)
+                        .map(Text)
+                        .map_err(de::Error::custom)
+                } else {
+                    Ok(Text(raw))
+                }
+            }
+        }
+
+        d.deserialize_newtype_struct(RAW_JSON, Visitor)
+    }
+}
+
+/// Serializes an amount as its raw number of units, for formats that have to round-trip exactly.
+/// Use with `#[serde(with = "units")]`.
+pub mod units {
+    use super::FixedPoint;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer, const SCALE: u32>(
+        amount: &FixedPoint<SCALE>,
+        s: S,
+    ) -> Result<S::Ok, S::Error> {
+        s.serialize_i64(amount.0)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>, const SCALE: u32>(
+        d: D,
+    ) -> Result<FixedPoint<SCALE>, D::Error> {
+        i64::deserialize(d).map(FixedPoint)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display() {
+        assert_eq!(Money::from_units(10000).to_string(), "1.0000");
+        assert_eq!(Money::from_units(-10000).to_string(), "-1.0000");
+        assert_eq!(Money::from_units(-1234500).to_string(), "-123.4500");
+        assert_eq!(Money::from_units(-5).to_string(), "-0.0005");
+        assert_eq!(Money::ZERO.to_string(), "0.0000");
+        assert_eq!(
+            Money::from_units(i64::MIN).to_string(),
+            "-922337203685477.5808"
+        );
+        assert_eq!(FixedPoint::<2>::from_units(12345).to_string(), "123.45");
+        assert_eq!(FixedPoint::<0>::from_units(-7).to_string(), "-7");
+    }
+
+    #[test]
+    fn parse() {
+        let parse = |s: &str| s.parse::<Money>().map(Money::units);
+        assert_eq!(parse("10"), Ok(100000));
+        assert_eq!(parse("10."), Ok(100000));
+        assert_eq!(parse("10.1"), Ok(101000));
+        assert_eq!(parse("+10.01"), Ok(100100));
+        assert_eq!(parse("-10.001"), Ok(-100010));
+        assert_eq!(parse("10.0001"), Ok(100001));
+        assert_eq!(parse("10.00001"), Err(ParseMoneyError::TooPrecise(4)));
+        assert_eq!(parse(".5"), Err(ParseMoneyError::Malformed));
+        assert_eq!(parse("1.2.3"), Err(ParseMoneyError::Malformed));
+        assert_eq!(parse("1.+2"), Err(ParseMoneyError::Malformed));
+        assert_eq!(parse("-+1"), Err(ParseMoneyError::Malformed));
+        assert_eq!(parse("922337203685477.5807"), Ok(i64::MAX));
+        assert_eq!(
+            parse("922337203685477.5808"),
+            Err(ParseMoneyError::TooLarge)
+        );
+        assert_eq!(
+            parse("99999999999999999999999"),
+            Err(ParseMoneyError::TooLarge)
+        );
+
+        assert_eq!("1.25".parse::<FixedPoint<2>>().unwrap().units(), 125);
+        assert_eq!(
+            "1.255".parse::<FixedPoint<2>>(),
+            Err(ParseMoneyError::TooPrecise(2))
+        );
+    }
+
+    #[test]
+    fn mul() {
+        let rate = "1.08521234".parse::<FixedPoint<8>>().unwrap();
+        assert_eq!(
+            Money::from_units(100000).checked_mul(rate),
+            Some(Money::from_units(108521))
+        );
+        assert_eq!(
+            Money::from_units(-100000).checked_mul(rate),
+            Some(Money::from_units(-108521))
+        );
+        assert_eq!(
+            Money::MAX.checked_mul(FixedPoint::<0>::from_units(1)),
+            Some(Money::MAX)
+        );
+        assert_eq!(Money::MAX.checked_mul(rate), None);
+    }
+
+    #[test]
+    fn serde() {
+        let amount = Money::from_units(-15000);
+        assert_eq!(serde_json::to_string(&amount).unwrap(), "\"-1.5000\"");
+        assert_eq!(serde_json::from_str::<Money>("\"-1.5\"").unwrap(), amount);
+        assert_eq!(
+            serde_json::from_str::<Money>("3").unwrap(),
+            Money::from_units(30000)
+        );
+
+        #[derive(Serialize, Deserialize)]
+        struct Raw {
+            #[serde(with = "units")]
+            amount: Money,
+        }
+        let raw = serde_json::to_string(&Raw { amount }).unwrap();
+        assert_eq!(raw, r#"{"amount":-15000}"#);
+        assert_eq!(serde_json::from_str::<Raw>(&raw).unwrap().amount, amount);
+    }
+
+    #[test]
+    fn deserialize_json() {
+        let json = |s: &str| serde_json::from_str::<Money>(s).ok();
+        assert_eq!(json("1.5"), Some(Money::from_units(15000)));
+        assert_eq!(json("1.0000"), Some(Money::from_units(10000)));
+        assert_eq!(json("\"1.5\""), Some(Money::from_units(15000)));
+        assert_eq!(json("922337203685477.5807"), Some(Money::MAX));
+        assert_eq!(json("1.00001"), None);
+        assert_eq!(json("1e3"), None);
+        assert_eq!(json("null"), None);
+        assert_eq!(
+            serde_json::from_str::<Vec<Option<Money>>>("[null, 2.25]").unwrap(),
+            vec![None, Some(Money::from_units(22500))]
+        );
+    }
+
+    #[test]
+    fn deserialize_csv() {
+        #[derive(Debug, PartialEq, Deserialize)]
+        struct Row {
+            amount: Money,
+            fee: Option<Money>,
+        }
+        let rows =
+            csv::Reader::from_reader("amount,fee\n1.5,\n1.0000,2\n-0.25,0.0001\n".as_bytes())
+                .deserialize()
+                .collect::<Result<Vec<Row>, _>>()
+                .unwrap();
+        assert_eq!(
+            rows,
+            vec![
+                Row {
+                    amount: Money::from_units(15000),
+                    fee: None
+                },
+                Row {
+                    amount: Money::from_units(10000),
+                    fee: Some(Money::from_units(20000))
+                },
+                Row {
+                    amount: Money::from_units(-2500),
+                    fee: Some(Money::from_units(1))
+                },
+            ]
+        );
+        assert!(
+            csv::Reader::from_reader("amount,fee\n1.00001,\n".as_bytes())
+                .deserialize::<Row>()
+                .next()
+                .unwrap()
+                .is_err()
+        );
+    }
+}
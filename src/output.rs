@@ -0,0 +1,327 @@
+use serde::Serialize;
+use std::io::{self, Write};
+use std::str::FromStr;
+use thiserror::Error;
+
+use crate::{Account, Balance, Currency, Money};
+
+/// Formats account balances can be written in. Every format writes amounts with four decimal
+/// places; JSON formats write them as strings so that no precision is lost to floats.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Csv,
+    /// A single JSON array of accounts.
+    Json,
+    /// One JSON account object per line.
+    JsonLines,
+    /// Aligned columns for people to read.
+    Table,
+}
+
+impl FromStr for Format {
+    type Err = OutputError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(Format::Csv),
+            "json" => Ok(Format::Json),
+            "jsonl" | "ndjson" => Ok(Format::JsonLines),
+            "table" => Ok(Format::Table),
+            _ => Err(OutputError::UnknownFormat(s.to_string())),
+        }
+    }
+}
+
+/// Orders balances can be written in. Rows are always ordered by client id and then currency after
+/// the chosen key, so the order is the same for the same balances on every run.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SortKey {
+    Client,
+    Available,
+    Held,
+    Total,
+}
+
+impl FromStr for SortKey {
+    type Err = OutputError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "client" => Ok(SortKey::Client),
+            "available" => Ok(SortKey::Available),
+            "held" => Ok(SortKey::Held),
+            "total" => Ok(SortKey::Total),
+            _ => Err(OutputError::UnknownSortKey(s.to_string())),
+        }
+    }
+}
+
+/// One row of output: an account's balances in one currency.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub struct Row {
+    pub client: u16,
+    #[serde(skip_serializing_if = "Currency::is_default")]
+    pub currency: Currency,
+    pub available: Money,
+    pub held: Money,
+    pub total: Money,
+    pub locked: bool,
+}
+
+/// The rows for `accounts`, one for each client and currency, in the order the accounts come in
+/// and then by currency. An account that holds nothing gets a single row of zeros in the default
+/// currency.
+pub fn rows<'a, I>(accounts: I) -> Vec<Row>
+where
+    I: IntoIterator<Item = &'a Account>,
+{
+    let mut rows = Vec::new();
+    for account in accounts {
+        let row = |currency: Currency, balance: Balance| Row {
+            client: account.id,
+            currency,
+            available: balance.available,
+            held: balance.held,
+            total: balance.total,
+            locked: account.locked,
+        };
+        if account.balances.is_empty() {
+            rows.push(row(Currency::DEFAULT, Balance::default()));
+        }
+        rows.extend(account.balances.iter().map(|(c, b)| row(*c, *b)));
+    }
+
+    rows
+}
+
+pub fn sort_rows(rows: &mut [Row], key: SortKey) {
+    match key {
+        SortKey::Client => rows.sort_by_key(|r| (r.client, r.currency)),
+        SortKey::Available => rows.sort_by_key(|r| (r.available, r.client, r.currency)),
+        SortKey::Held => rows.sort_by_key(|r| (r.held, r.client, r.currency)),
+        SortKey::Total => rows.sort_by_key(|r| (r.total, r.client, r.currency)),
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum OutputError {
+    #[error("unknown output format {0}")]
+    UnknownFormat(String),
+    #[error("unknown sort key {0}")]
+    UnknownSortKey(String),
+    #[error("could not write CSV: {0}")]
+    Csv(#[from] csv::Error),
+    #[error("could not write JSON: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("could not write output: {0}")]
+    Io(#[from] io::Error),
+}
+
+pub fn write_accounts<'a, W, I>(w: W, accounts: I, format: Format) -> Result<(), OutputError>
+where
+    W: Write,
+    I: IntoIterator<Item = &'a Account>,
+{
+    write_rows(w, &rows(accounts), format)
+}
+
+/// Writes rows in the order given. There is only a currency column if some row is in a currency
+/// other than the default.
+pub fn write_rows<W: Write>(w: W, rows: &[Row], format: Format) -> Result<(), OutputError> {
+    let with_currency = rows.iter().any(|r| !r.currency.is_default());
+    let mut header = vec!["client", "available", "held", "total", "locked"];
+    if with_currency {
+        header.insert(1, "currency");
+    }
+
+    write_records(w, rows, format, &header, |r| {
+        let mut cells = vec![
+            r.client.to_string(),
+            r.available.to_string(),
+            r.held.to_string(),
+            r.total.to_string(),
+            r.locked.to_string(),
+        ];
+        if with_currency {
+            cells.insert(1, r.currency.to_string());
+        }
+        cells
+    })
+}
+
+// writes anything serializable in any of the formats, with `cells` giving the columns of a record
+// for CSV and tables, and serde giving its fields for JSON
+pub(crate) fn write_records<'a, W, T, I, F>(
+    mut w: W,
+    records: I,
+    format: Format,
+    header: &[&str],
+    cells: F,
+) -> Result<(), OutputError>
+where
+    W: Write,
+    T: Serialize + 'a,
+    I: IntoIterator<Item = &'a T>,
+    F: Fn(&T) -> Vec<String>,
+{
+    match format {
+        Format::Csv => {
+            // like serde, no header without any records
+            let mut wtr = csv::Writer::from_writer(&mut w);
+            for (i, record) in records.into_iter().enumerate() {
+                if i == 0 {
+                    wtr.write_record(header)?;
+                }
+                wtr.write_record(cells(record))?;
+            }
+            wtr.flush()?;
+        }
+        Format::Json => {
+            serde_json::to_writer(&mut w, &records.into_iter().collect::<Vec<_>>())?;
+            writeln!(w)?;
+        }
+        Format::JsonLines => {
+            for record in records {
+                serde_json::to_writer(&mut w, record)?;
+                writeln!(w)?;
+            }
+        }
+        Format::Table => {
+            let rows = records.into_iter().map(cells).collect::<Vec<_>>();
+            write_table(&mut w, header, &rows)?;
+        }
+    }
+
+    w.flush()?;
+
+    Ok(())
+}
+
+fn write_table<W: Write>(w: &mut W, header: &[&str], rows: &[Vec<String>]) -> io::Result<()> {
+    let mut widths = header.iter().map(|h| h.len()).collect::<Vec<_>>();
+    for row in rows.iter() {
+        for (width, cell) in widths.iter_mut().zip(row.iter()) {
+            *width = (*width).max(cell.len());
+        }
+    }
+
+    // numbers are right-aligned so that the decimal points line up, and the last column is left
+    // unpadded so that lines don't end in whitespace
+    let header = header.iter().map(|h| h.to_string()).collect::<Vec<_>>();
+    for row in std::iter::once(&header).chain(rows.iter()) {
+        let last = row.len() - 1;
+        for (i, cell) in row.iter().enumerate() {
+            if i == last {
+                writeln!(w, "{}", cell)?;
+            } else {
+                write!(w, "{:>width$}  ", cell, width = widths[i])?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn balance(available: i64, held: i64) -> Balance {
+        Balance {
+            available: Money::from_units(available),
+            held: Money::from_units(held),
+            total: Money::from_units(available + held),
+        }
+    }
+
+    fn accounts() -> Vec<Account> {
+        vec![
+            Account::with_balance(1, balance(15000, 0), false),
+            Account::with_balance(12, balance(-12000, 1234567), true),
+        ]
+    }
+
+    fn written(format: Format) -> String {
+        let mut buf = Vec::new();
+        write_accounts(&mut buf, accounts().iter(), format).unwrap();
+        String::from_utf8(buf).unwrap()
+    }
+
+    #[test]
+    fn test_csv() {
+        assert_eq!(
+            written(Format::Csv),
+            "client,available,held,total,locked\n\
+             1,1.5000,0.0000,1.5000,false\n\
+             12,-1.2000,123.4567,122.2567,true\n"
+        );
+    }
+
+    #[test]
+    fn test_json() {
+        assert_eq!(
+            written(Format::Json),
+            "[{\"client\":1,\"available\":\"1.5000\",\"held\":\"0.0000\",\"total\":\"1.5000\",\"locked\":false},\
+             {\"client\":12,\"available\":\"-1.2000\",\"held\":\"123.4567\",\"total\":\"122.2567\",\"locked\":true}]\n"
+        );
+        assert_eq!(
+            written(Format::JsonLines),
+            "{\"client\":1,\"available\":\"1.5000\",\"held\":\"0.0000\",\"total\":\"1.5000\",\"locked\":false}\n\
+             {\"client\":12,\"available\":\"-1.2000\",\"held\":\"123.4567\",\"total\":\"122.2567\",\"locked\":true}\n"
+        );
+    }
+
+    #[test]
+    fn test_sort_rows() {
+        let mut accounts = accounts();
+        accounts.push(Account::with_balance(5, balance(15000, 0), false));
+        let ids = |key| {
+            let mut rows = rows(accounts.iter());
+            sort_rows(&mut rows, key);
+            rows.iter().map(|r| r.client).collect::<Vec<_>>()
+        };
+
+        assert_eq!(ids(SortKey::Client), vec![1, 5, 12]);
+        assert_eq!(ids(SortKey::Available), vec![12, 1, 5]);
+        assert_eq!(ids(SortKey::Held), vec![1, 5, 12]);
+        assert_eq!(ids(SortKey::Total), vec![1, 5, 12]);
+    }
+
+    #[test]
+    fn test_table() {
+        let expected = [
+            "client  available      held     total  locked",
+            "     1     1.5000    0.0000    1.5000  false",
+            "    12    -1.2000  123.4567  122.2567  true",
+            "",
+        ];
+        assert_eq!(written(Format::Table), expected.join("\n"));
+    }
+
+    #[test]
+    fn test_currencies() {
+        let usd = "USD".parse().unwrap();
+        let mut accounts = accounts();
+        accounts[0].set_balance(usd, balance(20000, 0));
+        accounts.push(Account::new(3));
+
+        let mut buf = Vec::new();
+        write_accounts(&mut buf, accounts.iter(), Format::Csv).unwrap();
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            "client,currency,available,held,total,locked\n\
+             1,,1.5000,0.0000,1.5000,false\n\
+             1,USD,2.0000,0.0000,2.0000,false\n\
+             12,,-1.2000,123.4567,122.2567,true\n\
+             3,,0.0000,0.0000,0.0000,false\n"
+        );
+
+        let mut buf = Vec::new();
+        write_accounts(&mut buf, accounts[..1].iter(), Format::JsonLines).unwrap();
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            "{\"client\":1,\"available\":\"1.5000\",\"held\":\"0.0000\",\"total\":\"1.5000\",\"locked\":false}\n\
+             {\"client\":1,\"currency\":\"USD\",\"available\":\"2.0000\",\"held\":\"0.0000\",\"total\":\"2.0000\",\"locked\":false}\n"
+        );
+    }
+}
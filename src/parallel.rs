@@ -0,0 +1,371 @@
+use std::collections::HashMap;
+use std::mem;
+use std::sync::mpsc;
+use std::thread;
+
+use crate::{
+    process::{self, Policy, State, TransactionProcessingError},
+    rates::SharedRates,
+    transaction::TransactionType::*,
+    Account, Transaction,
+};
+
+// transactions are handed to workers in batches to keep channel overhead down
+const BATCH_SIZE: usize = 1024;
+// number of batches that can be waiting for a worker before the dispatcher blocks
+const QUEUED_BATCHES: usize = 16;
+
+type Batch<K> = Vec<Job<K>>;
+// what a worker hands back: its shard of the state, and the transactions that failed
+type Shard<K> = (State, Vec<(K, TransactionProcessingError)>);
+
+/// Processes transactions on `workers` threads and merges the results into a single `State`.
+///
+/// Most transactions only touch the account of their client, so clients are sharded across workers
+/// and each worker owns the accounts and stored transactions of its clients outright. Transactions
+/// for the same client always go to the same worker in input order, so per-client ordering is the
+/// same as processing sequentially.
+///
+/// Transfers, and disputes, resolves and chargebacks of them, touch a second client's account. If
+/// that client belongs to another worker, that worker lends the account once it has processed
+/// everything before the transfer, and waits for it back; the other workers carry on.
+///
+/// Transaction ids are global though, so the dispatching thread keeps track of which client every
+/// deposit, withdrawal, conversion and transfer id was given to. When a later transaction reuses
+/// the id of another client's transaction, or of one that may have failed, the dispatcher asks the
+/// worker that had it whether it was stored, and waits for the answer. Reusing a stored id is
+/// rejected as already processed, and referring to another client's transaction as a client
+/// mismatch, the same as processing sequentially.
+///
+/// Each transaction is paired with a key of the caller's choosing (a line number, say) which is
+/// handed back alongside any error. Errors are returned in no particular order. Every worker, and
+/// the returned state, shares `rates`.
+pub fn process_parallel<K, I>(
+    transactions: I,
+    workers: usize,
+    policy: Policy,
+    rates: Option<SharedRates>,
+) -> (State, Vec<(K, TransactionProcessingError)>)
+where
+    K: Send + 'static,
+    I: IntoIterator<Item = (K, Transaction)>,
+{
+    assert!(workers > 0, "need at least one worker");
+
+    let mut pool = Workers::start(workers, policy, rates.clone());
+    let mut owners = HashMap::new();
+    let mut errors = Vec::new();
+    for (key, t) in transactions {
+        match check_owner(&mut owners, &mut pool, &t) {
+            Ok(borrow) => pool.send(key, t, borrow),
+            Err(error) => pool.reject(key, t, error),
+        }
+    }
+
+    let mut state = State::with_policy(policy);
+    state.rates = rates;
+    pool.finish(&mut state, &mut errors);
+
+    (state, errors)
+}
+
+// what a worker is asked to do
+enum Job<K> {
+    // processes a transaction, with another worker's account for the length of it if need be
+    Apply {
+        key: K,
+        transaction: Transaction,
+        borrow: Option<Borrow>,
+    },
+    // hands `client`'s account to the worker applying a transfer, and waits for it back
+    Lend {
+        client: u16,
+        account: mpsc::Sender<Option<Account>>,
+        back: mpsc::Receiver<Option<Account>>,
+    },
+    // fails a transaction the dispatcher knows has to fail, once its account has been looked at
+    Reject {
+        key: K,
+        transaction: Transaction,
+        error: TransactionProcessingError,
+    },
+    // runs a question about the worker's shard, once it has processed everything before it
+    Inspect(Box<dyn FnOnce(&State) + Send>),
+}
+
+// the borrowing side of a `Lend`. An account that doesn't exist yet is lent as `None`, and comes
+// back if the transfer opened it.
+struct Borrow {
+    client: u16,
+    account: mpsc::Receiver<Option<Account>>,
+    back: mpsc::Sender<Option<Account>>,
+}
+
+// the worker threads, each with its shard of the state
+struct Workers<K> {
+    senders: Vec<mpsc::SyncSender<Batch<K>>>,
+    handles: Vec<thread::JoinHandle<Shard<K>>>,
+    batches: Vec<Batch<K>>,
+}
+
+impl<K: Send + 'static> Workers<K> {
+    fn start(workers: usize, policy: Policy, rates: Option<SharedRates>) -> Self {
+        let mut senders = Vec::with_capacity(workers);
+        let mut handles = Vec::with_capacity(workers);
+        for _ in 0..workers {
+            let (sender, receiver) = mpsc::sync_channel::<Batch<K>>(QUEUED_BATCHES);
+            let mut shard = State::with_policy(policy);
+            shard.rates = rates.clone();
+            senders.push(sender);
+            handles.push(thread::spawn(move || {
+                let mut errors = Vec::new();
+                for batch in receiver {
+                    for job in batch {
+                        run(&mut shard, job, &mut errors);
+                    }
+                }
+
+                (shard, errors)
+            }));
+        }
+
+        Workers {
+            senders,
+            handles,
+            batches: (0..workers)
+                .map(|_| Vec::with_capacity(BATCH_SIZE))
+                .collect(),
+        }
+    }
+
+    fn shard(&self, client: u16) -> usize {
+        client as usize % self.senders.len()
+    }
+
+    // queues a transaction for its client's worker, along with a loan of `borrow`'s account if
+    // another worker holds it
+    fn send(&mut self, key: K, transaction: Transaction, borrow: Option<u16>) {
+        let shard = self.shard(transaction.client);
+        let lender = borrow.map(|client| (client, self.shard(client)));
+        match lender.filter(|&(_, lender)| lender != shard) {
+            None => self.push(
+                shard,
+                Job::Apply {
+                    key,
+                    transaction,
+                    borrow: None,
+                },
+            ),
+            Some((client, lender)) => {
+                let (lend, account) = mpsc::channel();
+                let (back, returned) = mpsc::channel();
+                self.push(
+                    shard,
+                    Job::Apply {
+                        key,
+                        transaction,
+                        borrow: Some(Borrow {
+                            client,
+                            account,
+                            back,
+                        }),
+                    },
+                );
+                self.push(
+                    lender,
+                    Job::Lend {
+                        client,
+                        account: lend,
+                        back: returned,
+                    },
+                );
+                // both workers wait on each other, so neither job can sit in a batch
+                self.flush(shard);
+                self.flush(lender);
+            }
+        }
+    }
+
+    fn reject(&mut self, key: K, transaction: Transaction, error: TransactionProcessingError) {
+        let shard = self.shard(transaction.client);
+        self.push(
+            shard,
+            Job::Reject {
+                key,
+                transaction,
+                error,
+            },
+        );
+    }
+
+    // asks the worker holding `client` a question about its shard and waits for the answer
+    fn inspect<R, F>(&mut self, client: u16, question: F) -> R
+    where
+        R: Send + 'static,
+        F: FnOnce(&State) -> R + Send + 'static,
+    {
+        let shard = self.shard(client);
+        let (reply, answer) = mpsc::channel();
+        self.push(
+            shard,
+            Job::Inspect(Box::new(move |state| {
+                let _ = reply.send(question(state));
+            })),
+        );
+        self.flush(shard);
+
+        answer.recv().expect("worker hung up")
+    }
+
+    fn push(&mut self, shard: usize, job: Job<K>) {
+        self.batches[shard].push(job);
+        if self.batches[shard].len() == BATCH_SIZE {
+            self.flush(shard);
+        }
+    }
+
+    fn flush(&mut self, shard: usize) {
+        if !self.batches[shard].is_empty() {
+            let batch = mem::replace(&mut self.batches[shard], Vec::with_capacity(BATCH_SIZE));
+            self.senders[shard].send(batch).expect("worker hung up");
+        }
+    }
+
+    // waits for the workers and merges their shards into `state`
+    fn finish(mut self, state: &mut State, errors: &mut Vec<(K, TransactionProcessingError)>) {
+        for shard in 0..self.senders.len() {
+            self.flush(shard);
+        }
+        drop(self.senders);
+
+        for handle in self.handles {
+            let (shard, shard_errors) = handle.join().expect("worker panicked");
+            state.accounts.extend(shard.accounts);
+            state.transactions.extend(shard.transactions);
+            errors.extend(shard_errors);
+        }
+    }
+}
+
+fn run<K>(shard: &mut State, job: Job<K>, errors: &mut Vec<(K, TransactionProcessingError)>) {
+    match job {
+        Job::Apply {
+            key,
+            transaction,
+            borrow,
+        } => {
+            if let Some(borrow) = &borrow {
+                if let Some(account) = borrow.account.recv().expect("lender hung up") {
+                    shard.accounts.insert(borrow.client, account);
+                }
+            }
+            if let Err(e) = process::process_one(shard, transaction) {
+                errors.push((key, e));
+            }
+            if let Some(borrow) = borrow {
+                let _ = borrow.back.send(shard.accounts.remove(&borrow.client));
+            }
+        }
+        Job::Lend {
+            client,
+            account,
+            back,
+        } => {
+            let _ = account.send(shard.accounts.remove(&client));
+            if let Some(account) = back.recv().expect("borrower hung up") {
+                shard.accounts.insert(client, account);
+            }
+        }
+        Job::Reject {
+            key,
+            transaction,
+            error,
+        } => errors.push((key, process::reject(shard, &transaction, error))),
+        Job::Inspect(question) => question(shard),
+    }
+}
+
+// the client a transaction id was given to, the client it was transferred to, and whether the
+// worker is known to have stored it
+struct Owner {
+    client: u16,
+    to_client: Option<u16>,
+    stored: bool,
+}
+
+// gives the id of deposits, withdrawals, conversions and transfers to their client, and checks that
+// disputes, resolves and chargebacks refer to a transaction of the same client. Returns the other
+// client whose account the transaction touches, if any.
+fn check_owner<K: Send + 'static>(
+    owners: &mut HashMap<u32, Owner>,
+    pool: &mut Workers<K>,
+    t: &Transaction,
+) -> Result<Option<u16>, TransactionProcessingError> {
+    match t.r#type {
+        Deposit | Withdrawal | Convert | Transfer => {
+            // a transaction the worker rejects before looking at its id doesn't take it
+            let to_client = match t.r#type {
+                Transfer => match t.to_client() {
+                    Ok(to_client) => Some(to_client),
+                    Err(_) => return Ok(None),
+                },
+                Convert if t.to_currency().is_err() => return Ok(None),
+                _ => None,
+            };
+            if t.amount().is_err() {
+                return Ok(None);
+            }
+
+            if is_stored(owners, pool, t.tx) {
+                return Err(TransactionProcessingError::TransactionAlreadyProcessed);
+            }
+            owners.insert(
+                t.tx,
+                Owner {
+                    client: t.client,
+                    to_client,
+                    stored: false,
+                },
+            );
+            Ok(to_client)
+        }
+        Dispute | Resolve | Chargeback => {
+            match owners.get(&t.tx) {
+                // the worker finds out for itself whether its own client's transaction was stored
+                Some(owner) if owner.client == t.client => return Ok(owner.to_client),
+                Some(_) => {}
+                None => return Ok(None),
+            }
+            if is_stored(owners, pool, t.tx) {
+                Err(TransactionProcessingError::TransactionClientMismatch)
+            } else {
+                Ok(None)
+            }
+        }
+        _ => Ok(None),
+    }
+}
+
+// whether a transaction with this id has been stored, asking the worker it was given to if that
+// isn't known yet. An id that wasn't stored is free again.
+fn is_stored<K: Send + 'static>(
+    owners: &mut HashMap<u32, Owner>,
+    pool: &mut Workers<K>,
+    tx: u32,
+) -> bool {
+    let owner = match owners.get_mut(&tx) {
+        Some(owner) => owner,
+        None => return false,
+    };
+    if !owner.stored {
+        owner.stored = pool.inspect(owner.client, move |shard| {
+            shard.transactions.contains_key(&tx)
+        });
+    }
+    let stored = owner.stored;
+    if !stored {
+        owners.remove(&tx);
+    }
+
+    stored
+}
@@ -1,18 +1,197 @@
-use std::collections::HashMap;
+use std::collections::{btree_map, BTreeMap, HashMap};
+use std::str::FromStr;
 use thiserror::Error;
 
-use crate::{transaction::TransactionType::*, Account, Transaction};
+use crate::{
+    audit::{AuditError, Before, Violation},
+    journal::{Balances, Entry, Journal, Operation},
+    rates::SharedRates,
+    store::{AccountStore, StoreError, TransactionStore},
+    transaction::{TransactionError, TransactionStatus::*, TransactionType::*},
+    Account, Converted, Currency, Money, StoredTransaction, Transaction, TransactionStatus,
+    TransactionType,
+};
 
-pub struct State {
-    pub transactions: HashMap<u32, Transaction>,
-    pub accounts: HashMap<u16, Account>,
+/// Knobs that change how transactions are processed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Policy {
+    /// Whether a transaction whose dispute was resolved may be disputed again.
+    pub allow_redispute: bool,
+    /// What disputing a withdrawal does. This should stay the same for the life of a state, since
+    /// a withdrawal disputed under one setting is resolved or charged back under the other.
+    pub withdrawal_disputes: WithdrawalDisputes,
+    /// Whether to check the engine's invariants after every transaction; see
+    /// [`audit`](crate::audit).
+    pub audit: bool,
+    /// Whether `lock` and `unlock` transactions are accepted. Leave this off for input from anyone
+    /// but administrators.
+    pub admin: bool,
+    /// Which transactions a locked account still accepts. `lock` and `unlock` are always
+    /// accepted.
+    pub locked_accounts: LockedAccounts,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Policy {
+            allow_redispute: true,
+            withdrawal_disputes: WithdrawalDisputes::Release,
+            audit: false,
+            admin: false,
+            locked_accounts: LockedAccounts::NONE,
+        }
+    }
+}
+
+/// What disputing a withdrawal does. Disputes of deposits always hold the deposited funds.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WithdrawalDisputes {
+    /// Disputes of withdrawals are rejected.
+    Disallow,
+    /// Nothing is held while a withdrawal is disputed, and a chargeback credits the withdrawn
+    /// amount back to the account. `held` never goes negative.
+    CreditOnChargeback,
+    /// Disputing a withdrawal releases its amount, which increases `available` and takes `held`
+    /// below zero, and resolving it holds the amount again. A chargeback undoes the dispute and
+    /// then credits the amount back, the same as `CreditOnChargeback`.
+    Release,
+}
+
+#[derive(Debug, Error)]
+#[error("unknown withdrawal dispute policy {0}")]
+pub struct UnknownWithdrawalDisputes(String);
+
+impl FromStr for WithdrawalDisputes {
+    type Err = UnknownWithdrawalDisputes;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "disallow" => Ok(WithdrawalDisputes::Disallow),
+            "credit-on-chargeback" => Ok(WithdrawalDisputes::CreditOnChargeback),
+            "release" => Ok(WithdrawalDisputes::Release),
+            _ => Err(UnknownWithdrawalDisputes(s.to_string())),
+        }
+    }
+}
+
+/// The transaction types a locked account still accepts, such as resolves and chargebacks so that
+/// disputes open when it was locked can be closed. A transfer or a dispute of one is checked
+/// against both accounts it touches.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct LockedAccounts {
+    // one bit for each transaction type
+    allowed: u16,
+}
+
+impl LockedAccounts {
+    /// Locked accounts accept nothing.
+    pub const NONE: LockedAccounts = LockedAccounts { allowed: 0 };
+
+    /// Locked accounts accept the given types.
+    pub fn allowing(types: &[TransactionType]) -> Self {
+        LockedAccounts {
+            allowed: types.iter().fold(0, |allowed, &t| allowed | 1 << t as u16),
+        }
+    }
+
+    /// Whether a locked account accepts a transaction of this type.
+    pub fn allows(&self, t: TransactionType) -> bool {
+        t == Lock || t == Unlock || self.allowed & 1 << t as u16 != 0
+    }
+}
+
+#[derive(Debug, Error)]
+#[error("unknown transaction type {0} for locked accounts")]
+pub struct UnknownLockedAccounts(String);
+
+impl FromStr for LockedAccounts {
+    type Err = UnknownLockedAccounts;
+
+    // `none`, or a comma separated list of transaction types
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "none" {
+            return Ok(LockedAccounts::NONE);
+        }
+
+        let types = s
+            .split(',')
+            .map(|t| match t.trim() {
+                "deposit" => Ok(Deposit),
+                "withdrawal" => Ok(Withdrawal),
+                "dispute" => Ok(Dispute),
+                "resolve" => Ok(Resolve),
+                "chargeback" => Ok(Chargeback),
+                "convert" => Ok(Convert),
+                "transfer" => Ok(Transfer),
+                t => Err(UnknownLockedAccounts(t.to_string())),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(LockedAccounts::allowing(&types))
+    }
+}
+
+impl Policy {
+    // moves a stored transaction through its dispute lifecycle, returning the new status if the
+    // transition is allowed
+    fn transition(
+        &self,
+        t: &StoredTransaction,
+        to: TransactionStatus,
+    ) -> Result<TransactionStatus, TransactionProcessingError> {
+        if t.r#type == Withdrawal
+            && to == Disputed
+            && self.withdrawal_disputes == WithdrawalDisputes::Disallow
+        {
+            return Err(WithdrawalDisputeNotAllowed);
+        }
+
+        let from = t.status;
+        match (from, to) {
+            (Processed, Disputed) | (Disputed, Resolved) | (Disputed, ChargedBack) => Ok(to),
+            (Resolved, Disputed) if self.allow_redispute => Ok(to),
+            _ => Err(IllegalTransition { from, to }),
+        }
+    }
+}
+
+/// Everything the engine knows about. Stored transactions live in `T` and accounts in `A`, both of
+/// which default to in-memory maps; see [`store`](crate::store) for alternatives. Accounts default
+/// to an ordered map so that they come out in client order.
+pub struct State<T = HashMap<u32, StoredTransaction>, A = BTreeMap<u16, Account>> {
+    pub transactions: T,
+    pub accounts: A,
+    pub policy: Policy,
+    /// Where every change to an account is recorded, if anywhere.
+    pub journal: Option<Journal>,
+    /// Where conversions get their rates. Without any, every conversion is rejected.
+    pub rates: Option<SharedRates>,
 }
 
 impl State {
     pub fn new() -> Self {
+        Self::with_policy(Policy::default())
+    }
+
+    pub fn with_policy(policy: Policy) -> Self {
+        Self::with_store(HashMap::new(), policy)
+    }
+}
+
+impl<T: TransactionStore> State<T> {
+    pub fn with_store(transactions: T, policy: Policy) -> Self {
+        Self::with_stores(transactions, BTreeMap::new(), policy)
+    }
+}
+
+impl<T: TransactionStore, A: AccountStore> State<T, A> {
+    pub fn with_stores(transactions: T, accounts: A, policy: Policy) -> Self {
         State {
-            transactions: HashMap::new(),
-            accounts: HashMap::new(),
+            transactions,
+            accounts,
+            policy,
+            journal: None,
+            rates: None,
         }
     }
 }
@@ -31,130 +210,495 @@ pub enum TransactionProcessingError {
     TransactionRequiresAmount,
     #[error("transaction does not exist")]
     TransactionDoesNotExist,
-    #[error("transaction disputed")]
-    TransactionDisputed,
-    #[error("transaction not disputed")]
-    TransactionNotDisputed,
+    #[error("transaction cannot go from {from} to {to}")]
+    IllegalTransition {
+        from: TransactionStatus,
+        to: TransactionStatus,
+    },
+    #[error("transaction belongs to a different client")]
+    TransactionClientMismatch,
+    #[error("transaction is in a different currency")]
+    TransactionCurrencyMismatch,
+    #[error("no rate to convert {from} to {to}")]
+    RateUnavailable { from: Currency, to: Currency },
+    #[error("withdrawals cannot be disputed")]
+    WithdrawalDisputeNotAllowed,
     #[error("account locked")]
     AccountLocked,
-    #[error("something went wrong")]
+    #[error("account not locked")]
+    AccountNotLocked,
+    #[error("transaction needs administrative access")]
+    AdminTransactionNotAllowed,
+    #[error("account error: {source}")]
     TransactionProcessingAccountError {
         #[from]
         source: crate::account::AccountError,
     },
-    #[error("something went wrong")]
+    #[error("transaction error: {source}")]
     TransactionProcessingTransactionError {
         #[from]
         source: crate::transaction::TransactionError,
     },
+    #[error("transaction store error: {source}")]
+    TransactionProcessingStoreError {
+        #[from]
+        source: StoreError,
+    },
+    /// The transaction broke one of the engine's invariants. Unlike every other error, the
+    /// transaction has been applied, and the state should no longer be trusted.
+    #[error("invariant violated: {source}")]
+    InvariantViolated {
+        #[from]
+        source: Violation,
+    },
 }
 use TransactionProcessingError::*;
 
-fn insert_if_not_exists(
-    txns: &mut HashMap<u32, Transaction>,
-    t: Transaction,
-) -> Result<(), TransactionProcessingError> {
-    #[allow(clippy::map_entry)]
-    if txns.contains_key(&t.tx) {
+impl TransactionProcessingError {
+    /// Short, stable name for the error, suitable for grouping rejections by cause.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            TransactionAlreadyProcessed => "TransactionAlreadyProcessed",
+            TransactionRequiresAmount => "TransactionRequiresAmount",
+            TransactionDoesNotExist => "TransactionDoesNotExist",
+            IllegalTransition { .. } => "IllegalTransition",
+            TransactionClientMismatch => "TransactionClientMismatch",
+            TransactionCurrencyMismatch => "TransactionCurrencyMismatch",
+            RateUnavailable { .. } => "RateUnavailable",
+            WithdrawalDisputeNotAllowed => "WithdrawalDisputeNotAllowed",
+            AccountLocked => "AccountLocked",
+            AccountNotLocked => "AccountNotLocked",
+            AdminTransactionNotAllowed => "AdminTransactionNotAllowed",
+            TransactionProcessingAccountError { source } => source.kind(),
+            TransactionProcessingTransactionError { source } => source.kind(),
+            TransactionProcessingStoreError { .. } => "StoreError",
+            InvariantViolated { .. } => "InvariantViolated",
+        }
+    }
+}
+
+// the stored form of a new deposit or withdrawal, which must not reuse an earlier one's id
+fn new_transaction<T: TransactionStore>(
+    txns: &T,
+    t: &Transaction,
+) -> Result<StoredTransaction, TransactionProcessingError> {
+    let amount = t.amount()?;
+    if txns.contains(t.tx)? {
         Err(TransactionAlreadyProcessed)
     } else {
-        txns.insert(t.tx, t);
+        Ok(StoredTransaction {
+            r#type: t.r#type,
+            client: t.client,
+            amount,
+            currency: t.currency,
+            converted: None,
+            to_client: None,
+            status: Processed,
+        })
+    }
+}
+
+// the stored form of a new conversion, with the amount it gives at the rate in effect
+fn new_conversion<T: TransactionStore>(
+    txns: &T,
+    rates: Option<&SharedRates>,
+    t: &Transaction,
+) -> Result<StoredTransaction, TransactionProcessingError> {
+    let to = t.to_currency()?;
+    let mut stored = new_transaction(txns, t)?;
+    let rate = rates
+        .and_then(|rates| rates.rate(t.currency, to, t.timestamp))
+        .ok_or(RateUnavailable {
+            from: t.currency,
+            to,
+        })?;
+    let amount = stored
+        .amount
+        .checked_mul(rate)
+        .ok_or(TransactionError::TransactionAmountTooLarge)?;
+    stored.converted = Some(Converted {
+        amount,
+        currency: to,
+    });
 
-        Ok(())
+    Ok(stored)
+}
+
+// the stored form of a new transfer, which belongs to the client it is from
+fn new_transfer<T: TransactionStore>(
+    txns: &T,
+    t: &Transaction,
+) -> Result<StoredTransaction, TransactionProcessingError> {
+    let to = t.to_client()?;
+    let mut stored = new_transaction(txns, t)?;
+    stored.to_client = Some(to);
+
+    Ok(stored)
+}
+
+// disputes, resolves and chargebacks may only refer to transactions of the client submitting
+// them, in the currency they were made in if one is given, and must move the referenced
+// transaction through a legal lifecycle transition
+fn transition_transaction<T: TransactionStore>(
+    txns: &T,
+    policy: &Policy,
+    by: &Transaction,
+    to: TransactionStatus,
+) -> Result<StoredTransaction, TransactionProcessingError> {
+    let mut t = txns.get(by.tx)?.ok_or(TransactionDoesNotExist)?;
+
+    if t.client != by.client {
+        return Err(TransactionClientMismatch);
+    }
+    if !by.currency.is_default() && t.currency != by.currency {
+        return Err(TransactionCurrencyMismatch);
     }
+
+    t.status = policy.transition(&t, to)?;
+
+    Ok(t)
 }
 
-fn get_transaction(
-    txns: &mut HashMap<u32, Transaction>,
-    id: u32,
-) -> Result<&mut Transaction, TransactionProcessingError> {
-    txns.get_mut(&id).ok_or(TransactionDoesNotExist)
+// under `CreditOnChargeback` a disputed withdrawal doesn't move any funds until it is charged back
+pub(crate) fn holds_nothing(policy: &Policy, t: &StoredTransaction) -> bool {
+    t.r#type == Withdrawal && policy.withdrawal_disputes == WithdrawalDisputes::CreditOnChargeback
 }
 
-fn get_disputed_transaction(
-    txns: &mut HashMap<u32, Transaction>,
-    id: u32,
-) -> Result<&mut Transaction, TransactionProcessingError> {
-    let t = get_transaction(txns, id)?;
+// deposits, conversions and transfers credit an account, so disputing them holds what they
+// credited
+fn credits(t: &StoredTransaction) -> bool {
+    matches!(t.r#type, Deposit | Convert | Transfer)
+}
 
-    if !t.disputed {
-        Err(TransactionNotDisputed)
+// disputing a deposit holds its funds, disputing a withdrawal releases them, and resolving does
+// the opposite
+fn hold_or_release(hold: bool) -> Operation {
+    if hold {
+        Operation::Hold
     } else {
-        Ok(t)
+        Operation::Release
     }
 }
 
-fn get_undisputed_transaction(
-    txns: &mut HashMap<u32, Transaction>,
-    id: u32,
-) -> Result<&mut Transaction, TransactionProcessingError> {
-    let t = get_transaction(txns, id)?;
+// one change to one of an account's balances
+#[derive(Clone, Copy)]
+struct Leg {
+    client: u16,
+    operation: Operation,
+    currency: Currency,
+    amount: Money,
+}
+
+// the balances an operation on a stored transaction changes. Everything happens in the
+// transaction's own account and currency, except that a conversion also credits the currency it
+// converted to and a transfer the client it was to, and that is what disputes hold and chargebacks
+// take back.
+fn legs(operation: Operation, t: &StoredTransaction) -> impl Iterator<Item = Leg> {
+    let own = Leg {
+        client: t.client,
+        operation,
+        currency: t.currency,
+        amount: t.amount,
+    };
+    let credited = match (t.converted, t.to_client) {
+        (None, None) => None,
+        (converted, to_client) => Some(Leg {
+            client: to_client.unwrap_or(t.client),
+            operation,
+            currency: converted.map_or(t.currency, |c| c.currency),
+            amount: converted.map_or(t.amount, |c| c.amount),
+        }),
+    };
+    let (first, second) = match credited {
+        None => (own, None),
+        Some(credited) => match operation {
+            Operation::Withdraw => (
+                own,
+                Some(Leg {
+                    operation: Operation::Deposit,
+                    ..credited
+                }),
+            ),
+            Operation::Chargeback => (
+                credited,
+                Some(Leg {
+                    operation: Operation::Deposit,
+                    ..own
+                }),
+            ),
+            _ => (credited, None),
+        },
+    };
 
-    if t.disputed {
-        Err(TransactionDisputed)
-    } else {
-        Ok(t)
+    std::iter::once(first).chain(second)
+}
+
+// the journal entry for opening `client`'s account on the way to applying `t`
+fn open_entry(t: &Transaction, client: u16) -> Entry {
+    let balances = Balances::of(&Account::new(client), t.currency);
+    Entry {
+        tx: t.tx,
+        client,
+        r#type: t.r#type,
+        operation: Operation::Open,
+        amount: Money::ZERO,
+        currency: t.currency,
+        before: balances,
+        after: balances,
+        reason: None,
+    }
+}
+
+// locks or unlocks an account, whatever it holds, with the reason going in the journal once for
+// each currency the account holds, so that every entry has the balances it leaves. Unlocking an
+// account that doesn't exist doesn't open one.
+fn set_locked<T: TransactionStore, A: AccountStore>(
+    state: &mut State<T, A>,
+    transaction: &Transaction,
+) -> Result<(), TransactionProcessingError> {
+    if !state.policy.admin {
+        return Err(AdminTransactionNotAllowed);
+    }
+    let reason = transaction.reason()?;
+
+    let lock = transaction.r#type == Lock;
+    let (mut account, opened) = match state.accounts.get(transaction.client)? {
+        Some(account) => (account, false),
+        None => (Account::new(transaction.client), true),
+    };
+    match (account.locked, lock) {
+        (true, true) => return Err(AccountLocked),
+        (false, false) => return Err(AccountNotLocked),
+        _ => {}
+    }
+
+    let mut currencies = account.balances.keys().copied().collect::<Vec<_>>();
+    if currencies.is_empty() {
+        currencies.push(Currency::DEFAULT);
     }
+    let before = account.clone();
+    account.locked = lock;
+    if let Some(journal) = state.journal.as_mut() {
+        if opened {
+            journal.record(open_entry(transaction, transaction.client))?;
+        }
+        for currency in currencies {
+            journal.record(Entry {
+                tx: transaction.tx,
+                client: transaction.client,
+                r#type: transaction.r#type,
+                operation: if lock {
+                    Operation::Lock
+                } else {
+                    Operation::Unlock
+                },
+                amount: Money::ZERO,
+                currency,
+                before: Balances::of(&before, currency),
+                after: Balances::of(&account, currency),
+                reason: Some(reason.to_string()),
+            })?;
+        }
+    }
+    state.accounts.put(account)?;
+
+    Ok(())
 }
 
 // designed this way so that if transactions were coming in from multiple sources, I could share
 // the state by putting it in a Mutex
-pub fn process_one(
-    state: &mut State,
+pub fn process_one<T: TransactionStore, A: AccountStore>(
+    state: &mut State<T, A>,
     transaction: Transaction,
 ) -> Result<(), TransactionProcessingError> {
-    let account = state
-        .accounts
-        .entry(transaction.client)
-        .or_insert_with(|| Account::new(transaction.client));
+    if !state.policy.audit {
+        return apply(state, transaction);
+    }
 
-    if account.locked {
-        return Err(AccountLocked);
+    let before = Before::take(state, &transaction)?;
+    let result = apply(state, transaction);
+    // a failed transaction shouldn't have changed anything either, so it is checked too
+    match before.check(state) {
+        Ok(()) => result,
+        Err(AuditError::Violation(source)) => Err(InvariantViolated { source }),
+        Err(AuditError::Store(source)) => Err(source.into()),
     }
+}
 
-    match transaction.r#type {
-        Deposit => {
-            let amount = transaction.amount()?;
-            insert_if_not_exists(&mut state.transactions, transaction)?;
+// the account of the transaction's client, which is opened if it doesn't exist, even if the
+// transaction goes on to fail
+fn open_account<T: TransactionStore, A: AccountStore>(
+    state: &mut State<T, A>,
+    transaction: &Transaction,
+) -> Result<Account, TransactionProcessingError> {
+    if let Some(account) = state.accounts.get(transaction.client)? {
+        return Ok(account);
+    }
 
-            account.deposit(amount);
-        }
-        Withdrawal => {
-            let amount = transaction.amount()?;
-            let id = transaction.tx;
-            insert_if_not_exists(&mut state.transactions, transaction)?;
+    let account = Account::new(transaction.client);
+    if let Some(journal) = state.journal.as_mut() {
+        journal.record(open_entry(transaction, transaction.client))?;
+    }
+    state.accounts.put(account.clone())?;
 
-            let result = account.withdraw(amount);
-            if result.is_err() {
-                // if withdrawing failed, remove the transaction from the processed map so it can't
-                // be later charged back
-                state.transactions.remove(&id).unwrap();
-                result?;
-            }
+    Ok(account)
+}
+
+// what `process_one` makes of a transaction that is known to fail with `error` once its account
+// has been opened and checked for a lock, for callers that know more about its id than the
+// transaction store does
+pub(crate) fn reject<T: TransactionStore, A: AccountStore>(
+    state: &mut State<T, A>,
+    transaction: &Transaction,
+    error: TransactionProcessingError,
+) -> TransactionProcessingError {
+    match open_account(state, transaction) {
+        Ok(account)
+            if account.locked && !state.policy.locked_accounts.allows(transaction.r#type) =>
+        {
+            AccountLocked
         }
+        Ok(_) => error,
+        Err(e) => e,
+    }
+}
+
+fn apply<T: TransactionStore, A: AccountStore>(
+    state: &mut State<T, A>,
+    transaction: Transaction,
+) -> Result<(), TransactionProcessingError> {
+    if transaction.is_admin() {
+        return set_locked(state, &transaction);
+    }
+
+    let account = open_account(state, &transaction)?;
+    let allowed = state.policy.locked_accounts.allows(transaction.r#type);
+    if account.locked && !allowed {
+        return Err(AccountLocked);
+    }
+
+    let (operation, stored) = match transaction.r#type {
+        Deposit => (
+            Operation::Deposit,
+            new_transaction(&state.transactions, &transaction)?,
+        ),
+        Withdrawal => (
+            Operation::Withdraw,
+            new_transaction(&state.transactions, &transaction)?,
+        ),
+        Convert => (
+            Operation::Withdraw,
+            new_conversion(&state.transactions, state.rates.as_ref(), &transaction)?,
+        ),
+        Transfer => (
+            Operation::Withdraw,
+            new_transfer(&state.transactions, &transaction)?,
+        ),
+        Lock | Unlock => unreachable!(),
         Dispute => {
             let disputed_transaction =
-                get_undisputed_transaction(&mut state.transactions, transaction.tx)?;
-            let amount = disputed_transaction.amount()?;
-            disputed_transaction.disputed = true;
+                transition_transaction(&state.transactions, &state.policy, &transaction, Disputed)?;
 
-            account.dispute(amount, disputed_transaction.r#type)
+            if holds_nothing(&state.policy, &disputed_transaction) {
+                (Operation::Mark, disputed_transaction)
+            } else {
+                (
+                    hold_or_release(credits(&disputed_transaction)),
+                    disputed_transaction,
+                )
+            }
         }
         Resolve => {
-            let disputed_transaction =
-                get_disputed_transaction(&mut state.transactions, transaction.tx)?;
-            disputed_transaction.disputed = false;
-            let amount = disputed_transaction.amount()?;
+            let resolved_transaction =
+                transition_transaction(&state.transactions, &state.policy, &transaction, Resolved)?;
 
-            account.resolve(amount, disputed_transaction.r#type)
+            if holds_nothing(&state.policy, &resolved_transaction) {
+                (Operation::Mark, resolved_transaction)
+            } else {
+                (
+                    hold_or_release(!credits(&resolved_transaction)),
+                    resolved_transaction,
+                )
+            }
         }
-        Chargeback => {
-            let disputed_transaction =
-                get_disputed_transaction(&mut state.transactions, transaction.tx)?;
-            let amount = disputed_transaction.amount()?;
+        Chargeback => (
+            Operation::Chargeback,
+            transition_transaction(
+                &state.transactions,
+                &state.policy,
+                &transaction,
+                ChargedBack,
+            )?,
+        ),
+    };
+
+    // every leg is applied to copies of the accounts before anything is written, so a transaction
+    // that fails part way changes nothing. The other account of a transfer is held to the same
+    // locked account policy, and if it doesn't exist yet it is only opened once the transfer has
+    // gone through.
+    let mut accounts = BTreeMap::new();
+    accounts.insert(account.id, account);
+    let mut opened = Vec::new();
+    let mut changes = Vec::new();
+    for leg in legs(operation, &stored) {
+        let account = match accounts.entry(leg.client) {
+            btree_map::Entry::Occupied(entry) => entry.into_mut(),
+            btree_map::Entry::Vacant(entry) => {
+                let account = match state.accounts.get(leg.client)? {
+                    Some(account) if account.locked && !allowed => return Err(AccountLocked),
+                    Some(account) => account,
+                    None => {
+                        opened.push(leg.client);
+                        Account::new(leg.client)
+                    }
+                };
+                entry.insert(account)
+            }
+        };
+        let before = Balances::of(account, leg.currency);
+        let (currency, amount) = (leg.currency, leg.amount);
+        match leg.operation {
+            Operation::Deposit => account.deposit(currency, amount)?,
+            Operation::Withdraw => account.withdraw(currency, amount)?,
+            Operation::Hold => account.hold(currency, amount)?,
+            Operation::Release => account.release(currency, amount)?,
+            Operation::Chargeback if holds_nothing(&state.policy, &stored) => {
+                account.chargeback_unheld_withdrawal(currency, amount)?
+            }
+            Operation::Chargeback => account.chargeback(currency, amount, stored.r#type)?,
+            Operation::Mark => {}
+            Operation::Open | Operation::Lock | Operation::Unlock => unreachable!(),
+        }
+        if state.journal.is_some() {
+            changes.push((leg, before, Balances::of(account, currency)));
+        }
+    }
+    // the stored transaction is only written once the account has taken the change
+    state.transactions.put(transaction.tx, stored)?;
 
-            account.chargeback(amount, disputed_transaction.r#type)
+    // journal entries are written ahead of the account, so the journal never misses a change
+    if let Some(journal) = state.journal.as_mut() {
+        for client in opened {
+            journal.record(open_entry(&transaction, client))?;
         }
+        for (leg, before, after) in changes {
+            journal.record(Entry {
+                tx: transaction.tx,
+                client: leg.client,
+                r#type: transaction.r#type,
+                operation: leg.operation,
+                amount: leg.amount,
+                currency: leg.currency,
+                before,
+                after,
+                reason: None,
+            })?;
+        }
+    }
+
+    // the accounts are only written back once the transaction has been applied in full
+    for account in accounts.into_values() {
+        state.accounts.put(account)?;
     }
 
     Ok(())
@@ -0,0 +1,159 @@
+//! Exchange rates for `convert` transactions.
+//!
+//! Conversions look their rate up in the state's [`RateProvider`], if it has one. [`FileRates`]
+//! reads rates from a CSV for offline runs, and a plain map gives fixed rates.
+
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+use std::sync::Arc;
+use thiserror::Error;
+
+use crate::{currency::Currency, money::FixedPoint};
+
+/// How much one unit of a currency is worth in another, to eight decimal places.
+pub type Rate = FixedPoint<8>;
+
+/// Where conversions get their exchange rates.
+pub trait RateProvider {
+    /// The rate from `from` to `to` in effect at `timestamp`, or the latest one when there is no
+    /// timestamp. `None` if there is no such rate.
+    fn rate(&self, from: Currency, to: Currency, timestamp: Option<u64>) -> Option<Rate>;
+}
+
+/// A rate provider that can be shared between threads, as the state holds it.
+pub type SharedRates = Arc<dyn RateProvider + Send + Sync>;
+
+/// Fixed rates, whatever the timestamp.
+impl RateProvider for HashMap<(Currency, Currency), Rate> {
+    fn rate(&self, from: Currency, to: Currency, _timestamp: Option<u64>) -> Option<Rate> {
+        self.get(&(from, to)).copied()
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum RatesError {
+    #[error("could not read rates: {0}")]
+    Csv(#[from] csv::Error),
+    #[error("could not read rates: {0}")]
+    Io(#[from] io::Error),
+    #[error("rate on line {line} is not positive")]
+    NotPositive { line: u64 },
+    #[error("rate on line {line} converts {currency} to itself")]
+    SameCurrency { line: u64, currency: Currency },
+}
+
+#[derive(Deserialize)]
+struct RateRecord {
+    timestamp: u64,
+    from: Currency,
+    to: Currency,
+    rate: Rate,
+}
+
+/// Rates read from a CSV with `timestamp,from,to,rate` columns, such as
+/// `1700000000,EUR,USD,1.0852`. Each rate is in effect from its timestamp until the next one for
+/// the same pair of currencies, and there is no rate for a time before the first.
+#[derive(Debug, Default)]
+pub struct FileRates {
+    rates: HashMap<(Currency, Currency), BTreeMap<u64, Rate>>,
+}
+
+impl FileRates {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, RatesError> {
+        Self::from_reader(File::open(path)?)
+    }
+
+    pub fn from_reader<R: Read>(reader: R) -> Result<Self, RatesError> {
+        let mut rdr = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(reader);
+        let headers = rdr.headers()?.clone();
+
+        let mut rates = FileRates::default();
+        for record in rdr.records() {
+            let record = record?;
+            let line = record.position().map_or(0, |p| p.line());
+            let r: RateRecord = record.deserialize(Some(&headers))?;
+            if r.rate <= Rate::ZERO {
+                return Err(RatesError::NotPositive { line });
+            }
+            if r.from == r.to {
+                return Err(RatesError::SameCurrency {
+                    line,
+                    currency: r.from,
+                });
+            }
+
+            rates
+                .rates
+                .entry((r.from, r.to))
+                .or_default()
+                .insert(r.timestamp, r.rate);
+        }
+
+        Ok(rates)
+    }
+}
+
+impl RateProvider for FileRates {
+    fn rate(&self, from: Currency, to: Currency, timestamp: Option<u64>) -> Option<Rate> {
+        let rates = self.rates.get(&(from, to))?;
+        let (_, rate) = match timestamp {
+            Some(timestamp) => rates.range(..=timestamp).next_back()?,
+            None => rates.iter().next_back()?,
+        };
+
+        Some(*rate)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATES: &str = "\
+timestamp,from,to,rate
+1000,EUR,USD,1.08
+2000,EUR,USD,1.1
+1500,USD,EUR,0.9
+";
+
+    #[test]
+    fn rates_by_timestamp() {
+        let rates = FileRates::from_reader(RATES.as_bytes()).unwrap();
+        let (eur, usd) = ("EUR".parse().unwrap(), "USD".parse().unwrap());
+        let rate = |s: &str| Some(s.parse::<Rate>().unwrap());
+
+        assert_eq!(rates.rate(eur, usd, Some(999)), None);
+        assert_eq!(rates.rate(eur, usd, Some(1000)), rate("1.08"));
+        assert_eq!(rates.rate(eur, usd, Some(1999)), rate("1.08"));
+        assert_eq!(rates.rate(eur, usd, Some(2000)), rate("1.1"));
+        assert_eq!(rates.rate(eur, usd, None), rate("1.1"));
+        assert_eq!(rates.rate(usd, eur, None), rate("0.9"));
+        assert_eq!(rates.rate(eur, Currency::DEFAULT, None), None);
+    }
+
+    #[test]
+    fn bad_rates() {
+        let bad = |row: &str| {
+            FileRates::from_reader(format!("timestamp,from,to,rate\n{}\n", row).as_bytes())
+                .unwrap_err()
+        };
+
+        assert!(matches!(
+            bad("1,EUR,USD,0"),
+            RatesError::NotPositive { line: 2 }
+        ));
+        assert!(matches!(
+            bad("1,EUR,eur,1"),
+            RatesError::SameCurrency { line: 2, .. }
+        ));
+        let malformed = bad("1,EUR,USD,1.000000001");
+        assert!(matches!(malformed, RatesError::Csv(_)));
+        assert!(malformed.to_string().contains("line: 2"));
+        assert!(matches!(bad("x,EUR,USD,1"), RatesError::Csv(_)));
+    }
+}
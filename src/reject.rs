@@ -0,0 +1,158 @@
+use std::collections::BTreeMap;
+use std::io::Write;
+
+use serde::Serialize;
+
+use crate::{input::Record, process::TransactionProcessingError};
+
+/// Kind recorded for rows that could not be read or deserialized into a `Transaction`.
+pub const INVALID_RECORD: &str = "InvalidRecord";
+
+/// A single input row that was not applied to the state.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Rejection {
+    pub line: Option<u64>,
+    pub tx: Option<u32>,
+    pub client: Option<u16>,
+    pub error: &'static str,
+    pub message: String,
+    pub raw: String,
+}
+
+impl Rejection {
+    /// Rejection for a row that could not be turned into a `Transaction`. The tx and client are
+    /// whatever could be recovered from the row.
+    pub fn invalid_record(
+        line: Option<u64>,
+        raw: String,
+        tx: Option<u32>,
+        client: Option<u16>,
+        message: String,
+    ) -> Self {
+        Rejection {
+            line,
+            tx,
+            client,
+            error: INVALID_RECORD,
+            message,
+            raw,
+        }
+    }
+
+    /// Rejection for a transaction that was read successfully but failed to process.
+    pub fn failed(
+        record: &Record,
+        tx: u32,
+        client: u16,
+        error: &TransactionProcessingError,
+    ) -> Self {
+        Rejection {
+            line: record.line,
+            tx: Some(tx),
+            client: Some(client),
+            error: error.kind(),
+            message: error.to_string(),
+            raw: record.raw.clone(),
+        }
+    }
+}
+
+/// Collects rejections, optionally writing each one out as a CSV row as it arrives, and keeps a
+/// count of rejections per error kind.
+pub struct RejectionReport {
+    sink: Option<csv::Writer<Box<dyn Write>>>,
+    counts: BTreeMap<&'static str, usize>,
+}
+
+impl RejectionReport {
+    pub fn new() -> Self {
+        RejectionReport {
+            sink: None,
+            counts: BTreeMap::new(),
+        }
+    }
+
+    pub fn with_sink(sink: Box<dyn Write>) -> Self {
+        RejectionReport {
+            sink: Some(csv::Writer::from_writer(sink)),
+            counts: BTreeMap::new(),
+        }
+    }
+
+    pub fn record(&mut self, rejection: Rejection) -> Result<(), csv::Error> {
+        *self.counts.entry(rejection.error).or_insert(0) += 1;
+
+        if let Some(sink) = self.sink.as_mut() {
+            sink.serialize(&rejection)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn counts(&self) -> &BTreeMap<&'static str, usize> {
+        &self.counts
+    }
+
+    pub fn total(&self) -> usize {
+        self.counts.values().sum()
+    }
+
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        match self.sink.as_mut() {
+            Some(sink) => sink.flush(),
+            None => Ok(()),
+        }
+    }
+
+    /// Writes a human readable summary of rejection counts per error kind.
+    pub fn write_summary<W: Write>(&self, mut w: W) -> std::io::Result<()> {
+        writeln!(w, "rejected {} rows", self.total())?;
+        for (kind, count) in self.counts.iter() {
+            writeln!(w, "  {}: {}", kind, count)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for RejectionReport {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_counts_per_kind() {
+        let record = Record {
+            line: Some(2),
+            raw: "dispute,1,1,".to_string(),
+        };
+        let mut report = RejectionReport::new();
+        for _ in 0..2 {
+            report
+                .record(Rejection::failed(
+                    &record,
+                    1,
+                    1,
+                    &TransactionProcessingError::TransactionDoesNotExist,
+                ))
+                .unwrap();
+        }
+        report
+            .record(Rejection::failed(
+                &record,
+                1,
+                1,
+                &TransactionProcessingError::AccountLocked,
+            ))
+            .unwrap();
+
+        assert_eq!(report.total(), 3);
+        assert_eq!(report.counts().get("TransactionDoesNotExist"), Some(&2));
+        assert_eq!(report.counts().get("AccountLocked"), Some(&1));
+    }
+}
@@ -0,0 +1,128 @@
+//! A line-based TCP server that applies transactions from many connections to one shared `State`.
+//!
+//! Each connection streams transactions in one of two formats, picked by its first line:
+//!
+//! * CSV: the first line is a header such as `type,client,tx,amount`, and every following line is
+//!   a record.
+//! * JSON lines: the first line starts with `{`, and every line is one transaction object such as
+//!   `{"type": "deposit", "client": 1, "tx": 1, "amount": "1.5"}`, read with
+//!   [`Transaction::from_json`].
+//!
+//! Lines starting with `!` are commands rather than transactions. `!dump` writes the balances of
+//! every account back on the connection in the same CSV format as the binary's output, followed
+//! by an empty line. Every transaction that is not applied gets a line of the form
+//! `rejected <line>: <kind>: <message>`.
+
+use std::io;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
+
+use crate::{
+    output,
+    process::{self, State},
+    reject::INVALID_RECORD,
+    Transaction,
+};
+
+pub type SharedState = Arc<Mutex<State>>;
+
+enum Format {
+    Csv(csv::StringRecord),
+    JsonLines,
+}
+
+/// Accepts connections on `listener` forever, handling each one on its own task.
+pub async fn serve(listener: TcpListener, state: SharedState) -> io::Result<()> {
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let state = state.clone();
+        tokio::spawn(async move {
+            // a connection going away doesn't affect anyone else
+            let _ = handle_connection(stream, state).await;
+        });
+    }
+}
+
+pub async fn handle_connection<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: S,
+    state: SharedState,
+) -> io::Result<()> {
+    let (reader, mut writer) = tokio::io::split(stream);
+    let mut lines = BufReader::new(reader).lines();
+    let mut format = None;
+    let mut line_number = 0;
+
+    while let Some(line) = lines.next_line().await? {
+        line_number += 1;
+        let line = line.trim_end_matches('\r');
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        if let Some(command) = line.strip_prefix('!') {
+            let response = match command.trim() {
+                "dump" => dump(&state)?,
+                other => format!("unknown command: {}\n", other).into_bytes(),
+            };
+            writer.write_all(&response).await?;
+            continue;
+        }
+
+        if format.is_none() {
+            if line.starts_with('{') {
+                format = Some(Format::JsonLines);
+            } else {
+                // the first line of a CSV stream is its header, there's no transaction in it
+                format = Some(Format::Csv(parse_csv_line(line)?));
+                continue;
+            }
+        }
+
+        let result = match parse(format.as_ref().expect("format is known"), line) {
+            Err(message) => Err((INVALID_RECORD, message)),
+            Ok(t) => {
+                let mut state = state.lock().expect("state lock poisoned");
+                process::process_one(&mut state, t).map_err(|e| (e.kind(), e.to_string()))
+            }
+        };
+
+        if let Err((kind, message)) = result {
+            let response = format!("rejected {}: {}: {}\n", line_number, kind, message);
+            writer.write_all(response.as_bytes()).await?;
+        }
+    }
+
+    writer.flush().await
+}
+
+fn parse_csv_line(line: &str) -> io::Result<csv::StringRecord> {
+    let mut rdr = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .from_reader(line.as_bytes());
+
+    match rdr.records().next() {
+        Some(record) => Ok(record?),
+        None => Ok(csv::StringRecord::new()),
+    }
+}
+
+fn parse(format: &Format, line: &str) -> Result<Transaction, String> {
+    match format {
+        Format::Csv(headers) => parse_csv_line(line)
+            .map_err(|e| e.to_string())?
+            .deserialize(Some(headers))
+            .map_err(|e| e.to_string()),
+        Format::JsonLines => Transaction::from_json(line).map_err(|e| e.to_string()),
+    }
+}
+
+fn dump(state: &SharedState) -> io::Result<Vec<u8>> {
+    let state = state.lock().expect("state lock poisoned");
+    let mut buf = Vec::new();
+    output::write_accounts(&mut buf, state.accounts.values(), output::Format::Csv)
+        .map_err(|e| io::Error::other(e.to_string()))?;
+    buf.push(b'\n');
+
+    Ok(buf)
+}
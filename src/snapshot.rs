@@ -0,0 +1,170 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::{self, Read, Write};
+use thiserror::Error;
+
+use crate::{
+    currency::Currency,
+    money::{units, Money},
+    process::State,
+    store::{AccountStore, StoreError, TransactionStore},
+    Account, Balance, StoredTransaction, TransactionType,
+};
+
+// version 2 added currencies, and reads version 1 snapshots as being in the default currency
+const VERSION: u32 = 2;
+
+#[derive(Debug, Error)]
+pub enum SnapshotError {
+    #[error("could not read or write snapshot: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("could not write snapshot: {0}")]
+    Io(#[from] io::Error),
+    #[error("snapshot version {0} is not supported")]
+    UnsupportedVersion(u32),
+    #[error("transaction {0} in the snapshot is a {1}, which is never stored")]
+    NotStored(u32, TransactionType),
+    #[error("store error: {0}")]
+    Store(#[from] StoreError),
+}
+
+// unlike the output format, amounts here are kept as raw ten-thousandths so that they round-trip
+// exactly, including negative held amounts. There is one of these for each currency an account
+// holds, or a single empty one for an account that holds nothing.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct SnapshotAccount {
+    client: u16,
+    #[serde(default, skip_serializing_if = "Currency::is_default")]
+    currency: Currency,
+    #[serde(with = "units")]
+    available: Money,
+    #[serde(with = "units")]
+    held: Money,
+    #[serde(with = "units")]
+    total: Money,
+    locked: bool,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct SnapshotTransaction {
+    tx: u32,
+    #[serde(flatten)]
+    transaction: StoredTransaction,
+}
+
+/// Everything needed to rebuild a `State`: every account, and every stored transaction along with
+/// where it is in its dispute lifecycle.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot {
+    version: u32,
+    accounts: Vec<SnapshotAccount>,
+    transactions: Vec<SnapshotTransaction>,
+}
+
+impl Snapshot {
+    pub fn capture<T: TransactionStore, A: AccountStore>(
+        state: &State<T, A>,
+    ) -> Result<Self, SnapshotError> {
+        let mut accounts = Vec::new();
+        for a in state.accounts.all() {
+            let empty = (Currency::DEFAULT, Balance::default());
+            let balances = a.balances.iter().map(|(c, b)| (*c, *b));
+            for (currency, balance) in balances.chain(a.balances.is_empty().then_some(empty)) {
+                accounts.push(SnapshotAccount {
+                    client: a.id,
+                    currency,
+                    available: balance.available,
+                    held: balance.held,
+                    total: balance.total,
+                    locked: a.locked,
+                });
+            }
+        }
+        accounts.sort_by_key(|a| (a.client, a.currency));
+
+        let mut transactions = state
+            .transactions
+            .entries()
+            .map(|entry| entry.map(|(tx, transaction)| SnapshotTransaction { tx, transaction }))
+            .collect::<Result<Vec<_>, _>>()?;
+        transactions.sort_by_key(|t| t.tx);
+
+        Ok(Snapshot {
+            version: VERSION,
+            accounts,
+            transactions,
+        })
+    }
+
+    /// Writes every account and transaction in the snapshot into `state`, replacing any with the
+    /// same id that are already there.
+    pub fn restore<T: TransactionStore, A: AccountStore>(
+        &self,
+        state: &mut State<T, A>,
+    ) -> Result<(), SnapshotError> {
+        // checked up front, so that a bad snapshot restores nothing
+        if let Some(t) = self
+            .transactions
+            .iter()
+            .find(|t| !t.transaction.r#type.is_stored())
+        {
+            return Err(SnapshotError::NotStored(t.tx, t.transaction.r#type));
+        }
+
+        let mut accounts = BTreeMap::new();
+        for a in self.accounts.iter() {
+            let account = accounts
+                .entry(a.client)
+                .or_insert_with(|| Account::new(a.client));
+            account.set_balance(
+                a.currency,
+                Balance {
+                    available: a.available,
+                    held: a.held,
+                    total: a.total,
+                },
+            );
+            account.locked = a.locked;
+        }
+        for account in accounts.into_values() {
+            state.accounts.put(account)?;
+        }
+
+        for t in self.transactions.iter() {
+            state.transactions.put(t.tx, t.transaction)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn write<W: Write>(&self, mut w: W) -> Result<(), SnapshotError> {
+        serde_json::to_writer(&mut w, self)?;
+        // a buffered writer would otherwise drop any error from its last write
+        w.flush()?;
+
+        Ok(())
+    }
+
+    pub fn read<R: Read>(r: R) -> Result<Self, SnapshotError> {
+        let snapshot: Snapshot = serde_json::from_reader(r)?;
+        if snapshot.version == 0 || snapshot.version > VERSION {
+            return Err(SnapshotError::UnsupportedVersion(snapshot.version));
+        }
+
+        Ok(snapshot)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unsupported_version() {
+        let json = r#"{"version":3,"accounts":[],"transactions":[]}"#;
+        assert!(matches!(
+            Snapshot::read(json.as_bytes()),
+            Err(SnapshotError::UnsupportedVersion(3))
+        ));
+    }
+}
@@ -0,0 +1,132 @@
+//! Per-client statements, listing every transaction applied to an account along with the balances
+//! it left behind, built from a [`journal`](crate::journal).
+
+use serde::Serialize;
+use std::borrow::Borrow;
+use std::io::Write;
+
+use crate::{
+    journal::{Entry, Operation},
+    output::{self, Format, OutputError},
+    Currency, Money, TransactionType,
+};
+
+/// One transaction on a statement, with the account's balances right after it.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct StatementLine {
+    pub client: u16,
+    pub tx: u32,
+    pub r#type: TransactionType,
+    pub amount: Money,
+    /// The currency of the amount and the balances.
+    #[serde(skip_serializing_if = "Currency::is_default")]
+    pub currency: Currency,
+    pub available: Money,
+    pub held: Money,
+    pub total: Money,
+    pub locked: bool,
+    /// Why an administrator locked or unlocked the account.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+impl From<&Entry> for StatementLine {
+    fn from(entry: &Entry) -> Self {
+        StatementLine {
+            client: entry.client,
+            tx: entry.tx,
+            r#type: entry.r#type,
+            amount: entry.amount,
+            currency: entry.currency,
+            available: entry.after.available,
+            held: entry.after.held,
+            total: entry.after.total,
+            locked: entry.after.locked,
+            reason: entry.reason.clone(),
+        }
+    }
+}
+
+/// Every transaction applied to `client`'s account, in the order they were applied.
+pub fn statement<I>(entries: I, client: u16) -> Vec<StatementLine>
+where
+    I: IntoIterator,
+    I::Item: Borrow<Entry>,
+{
+    entries
+        .into_iter()
+        .filter(|e| e.borrow().client == client)
+        .filter_map(|e| line(e.borrow()))
+        .collect()
+}
+
+/// The statements of every client in the journal, one after the other in client order.
+pub fn statements<I>(entries: I) -> Vec<StatementLine>
+where
+    I: IntoIterator,
+    I::Item: Borrow<Entry>,
+{
+    let mut lines = entries
+        .into_iter()
+        .filter_map(|e| line(e.borrow()))
+        .collect::<Vec<_>>();
+    // stable, so each client's lines stay in the order they were applied
+    lines.sort_by_key(|l| l.client);
+
+    lines
+}
+
+// opening an account isn't a transaction of its own, so it doesn't go on the statement
+fn line(entry: &Entry) -> Option<StatementLine> {
+    match entry.operation {
+        Operation::Open => None,
+        _ => Some(StatementLine::from(entry)),
+    }
+}
+
+/// Writes statement lines. As with balances, there is only a currency column if some line is in a
+/// currency other than the default, and there is only a reason column if some line has a reason.
+pub fn write_statement<W: Write>(
+    w: W,
+    lines: &[StatementLine],
+    format: Format,
+) -> Result<(), OutputError> {
+    let with_currency = lines.iter().any(|l| !l.currency.is_default());
+    let with_reason = lines.iter().any(|l| l.reason.is_some());
+    let mut header = vec![
+        "client",
+        "tx",
+        "type",
+        "amount",
+        "available",
+        "held",
+        "total",
+        "locked",
+    ];
+    if with_currency {
+        header.insert(4, "currency");
+    }
+    if with_reason {
+        header.push("reason");
+    }
+
+    output::write_records(w, lines, format, &header, |l| {
+        let mut cells = vec![
+            l.client.to_string(),
+            l.tx.to_string(),
+            l.r#type.to_string(),
+            l.amount.to_string(),
+            l.available.to_string(),
+            l.held.to_string(),
+            l.total.to_string(),
+            l.locked.to_string(),
+        ];
+        if with_currency {
+            cells.insert(4, l.currency.to_string());
+        }
+        if with_reason {
+            cells.push(l.reason.clone().unwrap_or_default());
+        }
+        cells
+    })
+}
@@ -0,0 +1,821 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+use crate::{
+    Account, Balance, Converted, Currency, Money, StoredTransaction, TransactionStatus,
+    TransactionType,
+};
+
+#[derive(Debug, PartialEq, Error)]
+pub enum StoreError {
+    // io::Error isn't PartialEq, so only keep its message around
+    #[error("i/o error: {0}")]
+    Io(String),
+    #[error("corrupt record for transaction {0}")]
+    Corrupt(u32),
+    #[error("corrupt record for account {0}")]
+    CorruptAccount(u16),
+    #[error("{path} is not {kind}, or was written by a version without a version header")]
+    UnknownFormat { path: String, kind: &'static str },
+    #[error("{path} is {kind} of version {found}, but only version {expected} can be read")]
+    UnsupportedVersion {
+        path: String,
+        kind: &'static str,
+        found: u32,
+        expected: u32,
+    },
+}
+
+impl From<io::Error> for StoreError {
+    fn from(e: io::Error) -> Self {
+        StoreError::Io(e.to_string())
+    }
+}
+
+// an error opening a store file, naming the file if it doesn't exist
+fn not_found(path: &Path, e: io::Error) -> StoreError {
+    match e.kind() {
+        io::ErrorKind::NotFound => StoreError::Io(format!("{}: {}", path.display(), e)),
+        _ => e.into(),
+    }
+}
+
+/// Storage for transactions that have been applied and may later be disputed.
+pub trait TransactionStore {
+    fn get(&self, id: u32) -> Result<Option<StoredTransaction>, StoreError>;
+
+    /// Inserts the transaction, or overwrites it if one with the same id is already stored.
+    fn put(&mut self, id: u32, t: StoredTransaction) -> Result<(), StoreError>;
+
+    fn remove(&mut self, id: u32) -> Result<(), StoreError>;
+
+    fn contains(&self, id: u32) -> Result<bool, StoreError> {
+        Ok(self.get(id)?.is_some())
+    }
+
+    /// Every stored transaction, in no particular order. Depending on the store this may be
+    /// expensive, it is meant for occasional whole-state operations like snapshots.
+    fn entries(
+        &self,
+    ) -> Box<dyn Iterator<Item = Result<(u32, StoredTransaction), StoreError>> + '_>;
+}
+
+/// Storage for client accounts.
+pub trait AccountStore {
+    fn get(&self, client: u16) -> Result<Option<Account>, StoreError>;
+
+    /// Inserts the account, or overwrites it if one with the same id is already stored.
+    fn put(&mut self, account: Account) -> Result<(), StoreError>;
+
+    fn remove(&mut self, client: u16) -> Result<(), StoreError>;
+
+    /// Every account, ordered by client id.
+    fn all(&self) -> Box<dyn Iterator<Item = &Account> + '_>;
+}
+
+impl AccountStore for BTreeMap<u16, Account> {
+    fn get(&self, client: u16) -> Result<Option<Account>, StoreError> {
+        Ok(BTreeMap::get(self, &client).cloned())
+    }
+
+    fn put(&mut self, account: Account) -> Result<(), StoreError> {
+        self.insert(account.id, account);
+
+        Ok(())
+    }
+
+    fn remove(&mut self, client: u16) -> Result<(), StoreError> {
+        BTreeMap::remove(self, &client);
+
+        Ok(())
+    }
+
+    fn all(&self) -> Box<dyn Iterator<Item = &Account> + '_> {
+        Box::new(self.values())
+    }
+}
+
+impl TransactionStore for HashMap<u32, StoredTransaction> {
+    fn get(&self, id: u32) -> Result<Option<StoredTransaction>, StoreError> {
+        Ok(HashMap::get(self, &id).copied())
+    }
+
+    fn put(&mut self, id: u32, t: StoredTransaction) -> Result<(), StoreError> {
+        self.insert(id, t);
+
+        Ok(())
+    }
+
+    fn remove(&mut self, id: u32) -> Result<(), StoreError> {
+        HashMap::remove(self, &id);
+
+        Ok(())
+    }
+
+    fn entries(
+        &self,
+    ) -> Box<dyn Iterator<Item = Result<(u32, StoredTransaction), StoreError>> + '_> {
+        Box::new(self.iter().map(|(id, t)| Ok((*id, *t))))
+    }
+}
+
+// every store file starts with a header of eight bytes saying what it holds and the version of its
+// layout as a little-endian u32, so that a file from another version is rejected rather than read
+// at the wrong record length. The version goes up whenever a layout changes.
+const HEADER_LEN: u64 = 12;
+
+// the header of a store file
+struct Header {
+    magic: &'static [u8; 8],
+    version: u32,
+    kind: &'static str,
+}
+
+impl Header {
+    fn bytes(&self) -> [u8; HEADER_LEN as usize] {
+        let mut buf = [0; HEADER_LEN as usize];
+        buf[0..8].copy_from_slice(self.magic);
+        buf[8..12].copy_from_slice(&self.version.to_le_bytes());
+
+        buf
+    }
+
+    // checks the start of a file that isn't empty
+    fn check(&self, path: &Path, buf: &[u8]) -> Result<(), StoreError> {
+        let path = path.display().to_string();
+        if buf.len() < HEADER_LEN as usize || &buf[0..8] != self.magic {
+            return Err(StoreError::UnknownFormat {
+                path,
+                kind: self.kind,
+            });
+        }
+
+        let found = u32::from_le_bytes([buf[8], buf[9], buf[10], buf[11]]);
+        if found != self.version {
+            return Err(StoreError::UnsupportedVersion {
+                path,
+                kind: self.kind,
+                found,
+                expected: self.version,
+            });
+        }
+
+        Ok(())
+    }
+}
+
+const TRANSACTIONS_HEADER: Header = Header {
+    magic: b"TXSTORE\0",
+    version: 1,
+    kind: "a transaction store",
+};
+
+// layout of a record in the file store, after the header, all integers little-endian:
+//
+//   0      present (0 means the slot is empty)
+//   1      type
+//   2      status
+//   3      flags: 1 if there is a converted amount, 2 if there is a client transferred to
+//   4..6   client
+//   6..8   client transferred to
+//   8..16  amount
+//   16..24 currency code, zero padded
+//   24..32 converted amount
+//   32..40 converted currency code
+const RECORD_LEN: u64 = 40;
+const CONVERTED_FLAG: u8 = 1;
+const TO_CLIENT_FLAG: u8 = 2;
+
+/// Transaction store backed by a file of fixed-width records indexed by transaction id.
+///
+/// Nothing is cached in memory, so memory use is constant regardless of how many transactions are
+/// stored. Slots for ids that were never written are holes in a sparse file, so disk usage grows
+/// with the number of stored transactions rather than with the largest id. Listing every entry
+/// does have to scan up to the largest id though.
+pub struct FileTransactionStore {
+    file: File,
+}
+
+impl FileTransactionStore {
+    /// Creates a new, empty store at `path`, truncating any existing file.
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<Self, StoreError> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        file.write_all(&TRANSACTIONS_HEADER.bytes())?;
+
+        Ok(FileTransactionStore { file })
+    }
+
+    /// Opens the store at `path`, keeping any transactions already in it, or creates it if it
+    /// doesn't exist.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, StoreError> {
+        Self::open_with(path.as_ref(), true)
+    }
+
+    /// Opens the store at `path`, which must already exist.
+    pub fn open_existing<P: AsRef<Path>>(path: P) -> Result<Self, StoreError> {
+        Self::open_with(path.as_ref(), false)
+    }
+
+    fn open_with(path: &Path, create: bool) -> Result<Self, StoreError> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(create)
+            .truncate(false)
+            .open(path)
+            .map_err(|e| not_found(path, e))?;
+
+        let mut header = Vec::new();
+        (&mut file).take(HEADER_LEN).read_to_end(&mut header)?;
+        if header.is_empty() && create {
+            file.write_all(&TRANSACTIONS_HEADER.bytes())?;
+        } else {
+            TRANSACTIONS_HEADER.check(path, &header)?;
+        }
+
+        Ok(FileTransactionStore { file })
+    }
+
+    fn read_record(&self, id: u32) -> Result<Option<[u8; RECORD_LEN as usize]>, StoreError> {
+        let offset = HEADER_LEN + id as u64 * RECORD_LEN;
+        if offset + RECORD_LEN > self.file.metadata()?.len() {
+            return Ok(None);
+        }
+
+        let mut buf = [0; RECORD_LEN as usize];
+        let mut file = &self.file;
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(&mut buf)?;
+
+        Ok(Some(buf))
+    }
+
+    fn write_record(&mut self, id: u32, buf: &[u8; RECORD_LEN as usize]) -> Result<(), StoreError> {
+        self.file
+            .seek(SeekFrom::Start(HEADER_LEN + id as u64 * RECORD_LEN))?;
+        self.file.write_all(buf)?;
+
+        Ok(())
+    }
+}
+
+fn encode_type(t: TransactionType) -> u8 {
+    match t {
+        TransactionType::Deposit => 0,
+        TransactionType::Withdrawal => 1,
+        TransactionType::Dispute => 2,
+        TransactionType::Resolve => 3,
+        TransactionType::Chargeback => 4,
+        TransactionType::Convert => 5,
+        TransactionType::Transfer => 6,
+        TransactionType::Lock => 7,
+        TransactionType::Unlock => 8,
+    }
+}
+
+fn decode_type(b: u8) -> Option<TransactionType> {
+    Some(match b {
+        0 => TransactionType::Deposit,
+        1 => TransactionType::Withdrawal,
+        2 => TransactionType::Dispute,
+        3 => TransactionType::Resolve,
+        4 => TransactionType::Chargeback,
+        5 => TransactionType::Convert,
+        6 => TransactionType::Transfer,
+        7 => TransactionType::Lock,
+        8 => TransactionType::Unlock,
+        _ => return None,
+    })
+}
+
+fn encode_status(s: TransactionStatus) -> u8 {
+    match s {
+        TransactionStatus::Processed => 0,
+        TransactionStatus::Disputed => 1,
+        TransactionStatus::Resolved => 2,
+        TransactionStatus::ChargedBack => 3,
+    }
+}
+
+fn decode_status(b: u8) -> Option<TransactionStatus> {
+    Some(match b {
+        0 => TransactionStatus::Processed,
+        1 => TransactionStatus::Disputed,
+        2 => TransactionStatus::Resolved,
+        3 => TransactionStatus::ChargedBack,
+        _ => return None,
+    })
+}
+
+fn decode_record(id: u32, buf: &[u8]) -> Result<Option<StoredTransaction>, StoreError> {
+    if buf[0] == 0 {
+        return Ok(None);
+    }
+    if buf[3] & !(CONVERTED_FLAG | TO_CLIENT_FLAG) != 0 {
+        return Err(StoreError::Corrupt(id));
+    }
+
+    let bytes_at = |start: usize| {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(&buf[start..start + 8]);
+        bytes
+    };
+    let money_at = |start: usize| Money::from_units(i64::from_le_bytes(bytes_at(start)));
+    let currency_at =
+        |start: usize| Currency::from_bytes(bytes_at(start)).ok_or(StoreError::Corrupt(id));
+
+    Ok(Some(StoredTransaction {
+        // only some types are ever stored, so a record of any other is corrupt
+        r#type: decode_type(buf[1])
+            .filter(|t| t.is_stored())
+            .ok_or(StoreError::Corrupt(id))?,
+        status: decode_status(buf[2]).ok_or(StoreError::Corrupt(id))?,
+        client: u16::from_le_bytes([buf[4], buf[5]]),
+        amount: money_at(8),
+        currency: currency_at(16)?,
+        converted: match buf[3] & CONVERTED_FLAG {
+            0 => None,
+            _ => Some(Converted {
+                amount: money_at(24),
+                currency: currency_at(32)?,
+            }),
+        },
+        to_client: match buf[3] & TO_CLIENT_FLAG {
+            0 => None,
+            _ => Some(u16::from_le_bytes([buf[6], buf[7]])),
+        },
+    }))
+}
+
+// number of records read at a time when scanning the whole file store
+const SCAN_RECORDS: usize = 4096;
+
+// walks the file store in blocks, skipping empty slots
+struct FileEntries<'a> {
+    file: &'a File,
+    next_id: u64,
+    end_id: u64,
+    buf: Vec<u8>,
+    pos: usize,
+}
+
+impl FileEntries<'_> {
+    fn next_record(&mut self) -> Result<Option<(u32, StoredTransaction)>, StoreError> {
+        loop {
+            if self.pos == self.buf.len() {
+                if self.next_id == self.end_id {
+                    return Ok(None);
+                }
+
+                let n = (self.end_id - self.next_id).min(SCAN_RECORDS as u64);
+                self.buf.resize((n * RECORD_LEN) as usize, 0);
+                let mut file = self.file;
+                file.seek(SeekFrom::Start(HEADER_LEN + self.next_id * RECORD_LEN))?;
+                file.read_exact(&mut self.buf)?;
+                self.pos = 0;
+            }
+
+            let id = (self.next_id) as u32;
+            let record = &self.buf[self.pos..self.pos + RECORD_LEN as usize];
+            self.next_id += 1;
+            self.pos += RECORD_LEN as usize;
+
+            if let Some(t) = decode_record(id, record)? {
+                return Ok(Some((id, t)));
+            }
+        }
+    }
+}
+
+impl Iterator for FileEntries<'_> {
+    type Item = Result<(u32, StoredTransaction), StoreError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.next_record() {
+            Ok(record) => record.map(Ok),
+            Err(e) => {
+                // stop after the first error rather than retrying the same block forever
+                self.next_id = self.end_id;
+                self.pos = self.buf.len();
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+impl TransactionStore for FileTransactionStore {
+    fn get(&self, id: u32) -> Result<Option<StoredTransaction>, StoreError> {
+        match self.read_record(id)? {
+            Some(buf) => decode_record(id, &buf),
+            None => Ok(None),
+        }
+    }
+
+    fn put(&mut self, id: u32, t: StoredTransaction) -> Result<(), StoreError> {
+        let mut buf = [0; RECORD_LEN as usize];
+        buf[0] = 1;
+        buf[1] = encode_type(t.r#type);
+        buf[2] = encode_status(t.status);
+        buf[4..6].copy_from_slice(&t.client.to_le_bytes());
+        buf[8..16].copy_from_slice(&t.amount.units().to_le_bytes());
+        buf[16..24].copy_from_slice(&t.currency.to_bytes());
+        if let Some(to_client) = t.to_client {
+            buf[3] |= TO_CLIENT_FLAG;
+            buf[6..8].copy_from_slice(&to_client.to_le_bytes());
+        }
+        if let Some(converted) = t.converted {
+            buf[3] |= CONVERTED_FLAG;
+            buf[24..32].copy_from_slice(&converted.amount.units().to_le_bytes());
+            buf[32..40].copy_from_slice(&converted.currency.to_bytes());
+        }
+
+        self.write_record(id, &buf)
+    }
+
+    fn remove(&mut self, id: u32) -> Result<(), StoreError> {
+        if self.read_record(id)?.is_some() {
+            self.write_record(id, &[0; RECORD_LEN as usize])?;
+        }
+
+        Ok(())
+    }
+
+    fn entries(
+        &self,
+    ) -> Box<dyn Iterator<Item = Result<(u32, StoredTransaction), StoreError>> + '_> {
+        let len = match self.file.metadata() {
+            Ok(metadata) => metadata.len(),
+            Err(e) => return Box::new(std::iter::once(Err(e.into()))),
+        };
+
+        Box::new(FileEntries {
+            file: &self.file,
+            next_id: 0,
+            end_id: len.saturating_sub(HEADER_LEN) / RECORD_LEN,
+            buf: Vec::new(),
+            pos: 0,
+        })
+    }
+}
+
+const ACCOUNTS_HEADER: Header = Header {
+    magic: b"ACCOUNTS",
+    version: 1,
+    kind: "an account log",
+};
+
+// layout of a record in the account log, after the header, all integers little-endian:
+//
+//   0..2    client
+//   2..10   currency code, zero padded
+//   10..18  available
+//   18..26  held
+//   26..34  total
+//   34      locked, or `ACCOUNT_REMOVED` if the account was removed
+//
+// each record holds one currency's balance, and an all-zero balance means the account no longer
+// holds that currency, or holds nothing at all
+const ACCOUNT_RECORD_LEN: usize = 35;
+const ACCOUNT_FLAG: usize = 34;
+const ACCOUNT_REMOVED: u8 = 2;
+
+/// Account store that keeps every account in memory and persists changes to an append-only log.
+///
+/// Opening the store replays the log, so balances carry over between runs. There are at most
+/// `u16::MAX + 1` accounts, so keeping them all in memory is cheap; the log itself grows with every
+/// change and can be rewritten down to one record per account with [`compact`](Self::compact).
+pub struct FileAccountStore {
+    path: PathBuf,
+    log: File,
+    accounts: BTreeMap<u16, Account>,
+}
+
+impl FileAccountStore {
+    /// Opens the log at `path`, replaying any accounts already in it, or creates it if it doesn't
+    /// exist.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, StoreError> {
+        Self::open_with(path.as_ref(), true)
+    }
+
+    /// Opens the log at `path`, which must already exist.
+    pub fn open_existing<P: AsRef<Path>>(path: P) -> Result<Self, StoreError> {
+        Self::open_with(path.as_ref(), false)
+    }
+
+    fn open_with(path: &Path, create: bool) -> Result<Self, StoreError> {
+        let path = path.to_path_buf();
+        let mut log = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(create)
+            .open(&path)
+            .map_err(|e| not_found(&path, e))?;
+
+        let mut buf = Vec::new();
+        log.read_to_end(&mut buf)?;
+        if buf.is_empty() && create {
+            log.write_all(&ACCOUNTS_HEADER.bytes())?;
+            buf.extend_from_slice(&ACCOUNTS_HEADER.bytes());
+        }
+        ACCOUNTS_HEADER.check(&path, &buf)?;
+
+        let mut accounts = BTreeMap::new();
+        let chunks = buf[HEADER_LEN as usize..].chunks_exact(ACCOUNT_RECORD_LEN);
+        // a trailing partial record means a write was interrupted, drop it so the next append
+        // starts on a record boundary
+        let complete = (buf.len() - chunks.remainder().len()) as u64;
+        for chunk in chunks {
+            let (client, currency, balance) = decode_balance(chunk)?;
+            if chunk[ACCOUNT_FLAG] == ACCOUNT_REMOVED {
+                accounts.remove(&client);
+            } else {
+                let account = accounts
+                    .entry(client)
+                    .or_insert_with(|| Account::new(client));
+                account.set_balance(currency, balance);
+                account.locked = chunk[ACCOUNT_FLAG] != 0;
+            }
+        }
+        if complete != buf.len() as u64 {
+            log.set_len(complete)?;
+        }
+
+        Ok(FileAccountStore {
+            path,
+            log,
+            accounts,
+        })
+    }
+
+    /// Rewrites the log so that it contains only the latest record for each account.
+    pub fn compact(&mut self) -> Result<(), StoreError> {
+        let tmp = self.path.with_extension("compact");
+        let mut f = File::create(&tmp)?;
+        f.write_all(&ACCOUNTS_HEADER.bytes())?;
+        for account in self.accounts.values() {
+            f.write_all(&encode_account(account, None))?;
+        }
+        f.sync_all()?;
+        std::fs::rename(&tmp, &self.path)?;
+
+        self.log = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .open(&self.path)?;
+
+        Ok(())
+    }
+}
+
+fn encode_balance(
+    client: u16,
+    currency: Currency,
+    balance: Balance,
+    flag: u8,
+) -> [u8; ACCOUNT_RECORD_LEN] {
+    let mut buf = [0; ACCOUNT_RECORD_LEN];
+    buf[0..2].copy_from_slice(&client.to_le_bytes());
+    buf[2..10].copy_from_slice(&currency.to_bytes());
+    buf[10..18].copy_from_slice(&balance.available.units().to_le_bytes());
+    buf[18..26].copy_from_slice(&balance.held.units().to_le_bytes());
+    buf[26..34].copy_from_slice(&balance.total.units().to_le_bytes());
+    buf[ACCOUNT_FLAG] = flag;
+
+    buf
+}
+
+// the records for an account, including empty ones for any currency it held in `previous` but no
+// longer does, and an empty one if it holds nothing so that the account itself is still recorded
+fn encode_account(account: &Account, previous: Option<&Account>) -> Vec<u8> {
+    let mut currencies = account.balances.keys().copied().collect::<Vec<_>>();
+    if let Some(previous) = previous {
+        currencies.extend(
+            previous
+                .balances
+                .keys()
+                .filter(|c| !account.balances.contains_key(c)),
+        );
+    }
+    if currencies.is_empty() {
+        currencies.push(Currency::DEFAULT);
+    }
+
+    currencies
+        .into_iter()
+        .flat_map(|currency| {
+            encode_balance(
+                account.id,
+                currency,
+                account.balance(currency),
+                account.locked as u8,
+            )
+        })
+        .collect()
+}
+
+fn decode_balance(buf: &[u8]) -> Result<(u16, Currency, Balance), StoreError> {
+    let money_at = |start: usize| {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(&buf[start..start + 8]);
+        Money::from_units(i64::from_le_bytes(bytes))
+    };
+    let client = u16::from_le_bytes([buf[0], buf[1]]);
+    let mut currency = [0; 8];
+    currency.copy_from_slice(&buf[2..10]);
+    let currency = Currency::from_bytes(currency).ok_or(StoreError::CorruptAccount(client))?;
+
+    Ok((
+        client,
+        currency,
+        Balance {
+            available: money_at(10),
+            held: money_at(18),
+            total: money_at(26),
+        },
+    ))
+}
+
+impl AccountStore for FileAccountStore {
+    fn get(&self, client: u16) -> Result<Option<Account>, StoreError> {
+        Ok(self.accounts.get(&client).cloned())
+    }
+
+    fn put(&mut self, account: Account) -> Result<(), StoreError> {
+        let previous = self.accounts.get(&account.id);
+        self.log.write_all(&encode_account(&account, previous))?;
+        self.accounts.insert(account.id, account);
+
+        Ok(())
+    }
+
+    fn remove(&mut self, client: u16) -> Result<(), StoreError> {
+        if self.accounts.contains_key(&client) {
+            let buf = encode_balance(
+                client,
+                Currency::DEFAULT,
+                Balance::default(),
+                ACCOUNT_REMOVED,
+            );
+            self.log.write_all(&buf)?;
+            self.accounts.remove(&client);
+        }
+
+        Ok(())
+    }
+
+    fn all(&self) -> Box<dyn Iterator<Item = &Account> + '_> {
+        Box::new(self.accounts.values())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_store_round_trip() {
+        let path = std::env::temp_dir().join(format!("transactions-store-{}", std::process::id()));
+        let mut store = FileTransactionStore::create(&path).unwrap();
+
+        let t = StoredTransaction {
+            r#type: TransactionType::Withdrawal,
+            client: 513,
+            amount: Money::from_units(123456789),
+            currency: "EUR".parse().unwrap(),
+            converted: Some(Converted {
+                amount: Money::from_units(-1),
+                currency: "USD".parse().unwrap(),
+            }),
+            to_client: Some(65535),
+            status: TransactionStatus::Disputed,
+        };
+        assert_eq!(store.get(7).unwrap(), None);
+        store.put(7, t).unwrap();
+        assert_eq!(store.get(7).unwrap(), Some(t));
+        assert_eq!(store.get(6).unwrap(), None);
+        assert_eq!(store.get(u32::MAX).unwrap(), None);
+
+        store.put(9000, t).unwrap();
+        let entries = store.entries().collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(entries, vec![(7, t), (9000, t)]);
+
+        store.put(u32::MAX, t).unwrap();
+        assert_eq!(store.get(u32::MAX).unwrap(), Some(t));
+
+        store.remove(7).unwrap();
+        assert_eq!(store.get(7).unwrap(), None);
+        assert!(store.contains(u32::MAX).unwrap());
+
+        // a record of a type that is never stored can't be read back
+        let lock = StoredTransaction {
+            r#type: TransactionType::Lock,
+            ..t
+        };
+        store.put(8, lock).unwrap();
+        assert_eq!(store.get(8), Err(StoreError::Corrupt(8)));
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_file_account_store_reopen() {
+        let path =
+            std::env::temp_dir().join(format!("transactions-accounts-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let eur = "EUR".parse().unwrap();
+        let mut account = Account::new(3);
+        account
+            .deposit(Currency::DEFAULT, Money::from_units(15000))
+            .unwrap();
+        account.deposit(eur, Money::from_units(100)).unwrap();
+        {
+            let mut store = FileAccountStore::open(&path).unwrap();
+            store.put(Account::new(3)).unwrap();
+            store.put(account.clone()).unwrap();
+            // emptying a currency writes an empty record for it
+            account.withdraw(eur, Money::from_units(100)).unwrap();
+            store.put(account.clone()).unwrap();
+            store.put(Account::new(9)).unwrap();
+        }
+
+        // simulate a write that was cut short
+        let mut f = OpenOptions::new().append(true).open(&path).unwrap();
+        f.write_all(&[1, 2, 3]).unwrap();
+        drop(f);
+
+        let mut store = FileAccountStore::open(&path).unwrap();
+        assert_eq!(store.get(3).unwrap(), Some(account));
+        assert_eq!(store.get(9).unwrap(), Some(Account::new(9)));
+        assert_eq!(store.get(4).unwrap(), None);
+        assert_eq!(
+            std::fs::metadata(&path).unwrap().len(),
+            HEADER_LEN + 6 * ACCOUNT_RECORD_LEN as u64
+        );
+
+        store.compact().unwrap();
+        assert_eq!(
+            std::fs::metadata(&path).unwrap().len(),
+            HEADER_LEN + 2 * ACCOUNT_RECORD_LEN as u64
+        );
+        store.put(Account::new(4)).unwrap();
+        store.remove(9).unwrap();
+        drop(store);
+
+        let store = FileAccountStore::open(&path).unwrap();
+        assert_eq!(store.all().map(|a| a.id).collect::<Vec<_>>(), vec![3, 4]);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_files_of_other_versions_are_rejected() {
+        let path = std::env::temp_dir().join(format!("transactions-header-{}", std::process::id()));
+
+        // a record from before files had headers
+        std::fs::write(&path, [1; RECORD_LEN as usize]).unwrap();
+        assert_eq!(
+            FileTransactionStore::open(&path).err(),
+            Some(StoreError::UnknownFormat {
+                path: path.display().to_string(),
+                kind: "a transaction store",
+            })
+        );
+        assert!(matches!(
+            FileAccountStore::open(&path).err(),
+            Some(StoreError::UnknownFormat { .. })
+        ));
+
+        let mut header = TRANSACTIONS_HEADER.bytes();
+        header[8] = 2;
+        std::fs::write(&path, header).unwrap();
+        assert_eq!(
+            FileTransactionStore::open(&path).err(),
+            Some(StoreError::UnsupportedVersion {
+                path: path.display().to_string(),
+                kind: "a transaction store",
+                found: 2,
+                expected: 1,
+            })
+        );
+
+        // a new store is given a header, and opens again
+        std::fs::remove_file(&path).unwrap();
+        FileTransactionStore::open(&path).unwrap();
+        FileTransactionStore::open(&path).unwrap();
+        assert_eq!(
+            std::fs::read(&path).unwrap(),
+            TRANSACTIONS_HEADER.bytes().to_vec()
+        );
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -1,54 +1,79 @@
 use serde::de;
-use serde::{Deserialize, Deserializer};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::fmt;
 use thiserror::Error;
 
-fn money_string_to_u64(s: String) -> Result<u64, TransactionError> {
-    let mut pieces = s.split('.');
+use crate::{
+    currency::Currency,
+    money::{units, Money, ParseMoneyError, Text},
+};
 
-    let whole = pieces.next().ok_or(TransactionAmountImproperlyFormatted)?;
-    let whole = whole
-        .parse::<u64>()
-        .or(Err(TransactionAmountImproperlyFormatted))?;
-
-    let mut fractional = 0;
-    if let Some(piece) = pieces.next() {
-        if piece.len() > 4 {
-            return Err(TransactionAmountImproperlyFormatted);
-        }
-
-        let padded = format!("{:0<4}", piece);
-        fractional = padded
-            .parse::<u64>()
-            .or(Err(TransactionAmountImproperlyFormatted))?;
-    }
-
-    if pieces.next().is_some() {
+// amounts in transactions are never negative, and must fit in a balance
+fn parse_amount(s: &str) -> Result<Money, TransactionError> {
+    if s.starts_with('-') {
         return Err(TransactionAmountImproperlyFormatted);
     }
 
-    Ok((whole * 10000) + fractional)
+    s.parse::<Money>().map_err(|e| match e {
+        ParseMoneyError::TooLarge => TransactionAmountTooLarge,
+        ParseMoneyError::Malformed | ParseMoneyError::TooPrecise(_) => {
+            TransactionAmountImproperlyFormatted
+        }
+    })
 }
 
-fn amount_deserializer<'de, D: Deserializer<'de>>(d: D) -> Result<Option<u64>, D::Error> {
-    let buf = String::deserialize(d)?;
-
-    if buf.is_empty() {
-        Ok(None)
-    } else {
-        Ok(Some(money_string_to_u64(buf).map_err(de::Error::custom)?))
+// amounts are taken as text, from a CSV field or a JSON string or number, and an empty one is
+// the same as none
+fn amount_deserializer<'de, D: Deserializer<'de>>(d: D) -> Result<Option<Money>, D::Error> {
+    match Option::<Text>::deserialize(d)? {
+        Some(Text(text)) if !text.is_empty() => {
+            parse_amount(&text).map(Some).map_err(de::Error::custom)
+        }
+        _ => Ok(None),
     }
 }
 
 #[derive(Debug, PartialEq, Error)]
+#[allow(clippy::enum_variant_names)]
 pub enum TransactionError {
     #[error("transaction needs amount")]
     TransactionNeedsAmount,
     #[error("transaction amount improperly formatted")]
     TransactionAmountImproperlyFormatted,
+    #[error("transaction amount too large")]
+    TransactionAmountTooLarge,
+    #[error("transaction amount is negative")]
+    TransactionAmountNegative,
+    #[error("conversion needs a currency to convert to")]
+    TransactionNeedsTargetCurrency,
+    #[error("conversion is to the currency it is from")]
+    TransactionConvertsToSameCurrency,
+    #[error("transfer needs a client to transfer to")]
+    TransactionNeedsTargetClient,
+    #[error("transfer is to the client it is from")]
+    TransactionTransfersToSameClient,
+    #[error("transaction needs a reason")]
+    TransactionNeedsReason,
 }
 use TransactionError::*;
 
-#[derive(Clone, Copy, Debug, Deserialize)]
+impl TransactionError {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            TransactionNeedsAmount => "TransactionNeedsAmount",
+            TransactionAmountImproperlyFormatted => "TransactionAmountImproperlyFormatted",
+            TransactionAmountTooLarge => "TransactionAmountTooLarge",
+            TransactionAmountNegative => "TransactionAmountNegative",
+            TransactionNeedsTargetCurrency => "TransactionNeedsTargetCurrency",
+            TransactionConvertsToSameCurrency => "TransactionConvertsToSameCurrency",
+            TransactionNeedsTargetClient => "TransactionNeedsTargetClient",
+            TransactionTransfersToSameClient => "TransactionTransfersToSameClient",
+            TransactionNeedsReason => "TransactionNeedsReason",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum TransactionType {
     Deposit,
@@ -56,6 +81,46 @@ pub enum TransactionType {
     Dispute,
     Resolve,
     Chargeback,
+    /// Moves an amount from one of the client's currencies to another.
+    Convert,
+    /// Moves an amount from the client's account to another client's.
+    Transfer,
+    /// Locks the client's account. Only accepted from administrators.
+    Lock,
+    /// Unlocks the client's account, such as after a chargeback has been reviewed. Only accepted
+    /// from administrators.
+    Unlock,
+}
+
+impl fmt::Display for TransactionType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            TransactionType::Deposit => "deposit",
+            TransactionType::Withdrawal => "withdrawal",
+            TransactionType::Dispute => "dispute",
+            TransactionType::Resolve => "resolve",
+            TransactionType::Chargeback => "chargeback",
+            TransactionType::Convert => "convert",
+            TransactionType::Transfer => "transfer",
+            TransactionType::Lock => "lock",
+            TransactionType::Unlock => "unlock",
+        };
+
+        f.write_str(s)
+    }
+}
+
+impl TransactionType {
+    /// Whether transactions of this type are stored, so that they can be disputed later.
+    pub fn is_stored(self) -> bool {
+        matches!(
+            self,
+            TransactionType::Deposit
+                | TransactionType::Withdrawal
+                | TransactionType::Convert
+                | TransactionType::Transfer
+        )
+    }
 }
 
 /*
@@ -71,28 +136,154 @@ pub enum TransactionType {
  * which works with JSON but doesn't seem to work with CSV because of
  * https://github.com/BurntSushi/rust-csv/issues/211
  */
-#[derive(Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize)]
 pub struct Transaction {
     pub r#type: TransactionType,
-    // would consider using fixed-point if needed to do anything more complex than adding and
-    // subtracting
-    #[serde(deserialize_with = "amount_deserializer")]
-    pub amount: Option<u64>,
+    #[serde(default, deserialize_with = "amount_deserializer")]
+    pub amount: Option<Money>,
     pub client: u16,
     pub tx: u32,
-    #[serde(skip_deserializing, default = "bool_false")]
-    pub disputed: bool,
+    /// The currency of a deposit, withdrawal or transfer, or the one a conversion is from. Disputes,
+    /// resolves and chargebacks always apply in the currency of the transaction they refer to, and
+    /// may leave this out.
+    #[serde(default)]
+    pub currency: Currency,
+    /// The currency a conversion is to.
+    #[serde(default)]
+    pub to_currency: Option<Currency>,
+    /// When the transaction happened, in seconds since the Unix epoch, which picks the rate a
+    /// conversion uses. Conversions without one use the latest rate.
+    #[serde(default)]
+    pub timestamp: Option<u64>,
+    /// The client a transfer is to. The transfer is made by, and belongs to, `client`.
+    #[serde(default)]
+    pub to_client: Option<u16>,
+    /// Why an account is being locked or unlocked, as a code such as `REVIEWED`.
+    #[serde(default)]
+    pub reason: Option<String>,
 }
 
 impl Transaction {
-    pub fn amount(&self) -> Result<u64, TransactionError> {
-        self.amount.ok_or(TransactionNeedsAmount)
+    /// A transaction in the default currency, with none of the optional columns set.
+    pub fn new(r#type: TransactionType, client: u16, tx: u32, amount: Option<Money>) -> Self {
+        Transaction {
+            r#type,
+            amount,
+            client,
+            tx,
+            currency: Currency::DEFAULT,
+            to_currency: None,
+            timestamp: None,
+            to_client: None,
+            reason: None,
+        }
+    }
+
+    /// The amount of a deposit or withdrawal, which must be there and must not be negative.
+    pub fn amount(&self) -> Result<Money, TransactionError> {
+        match self.amount {
+            Some(amount) if amount.is_negative() => Err(TransactionAmountNegative),
+            Some(amount) => Ok(amount),
+            None => Err(TransactionNeedsAmount),
+        }
+    }
+
+    /// The currency a conversion is to, which must be there and must not be the one it is from.
+    pub fn to_currency(&self) -> Result<Currency, TransactionError> {
+        match self.to_currency {
+            Some(to) if to == self.currency => Err(TransactionConvertsToSameCurrency),
+            Some(to) => Ok(to),
+            None => Err(TransactionNeedsTargetCurrency),
+        }
+    }
+
+    /// The client a transfer is to, which must be there and must not be the one it is from.
+    pub fn to_client(&self) -> Result<u16, TransactionError> {
+        match self.to_client {
+            Some(to) if to == self.client => Err(TransactionTransfersToSameClient),
+            Some(to) => Ok(to),
+            None => Err(TransactionNeedsTargetClient),
+        }
+    }
+
+    /// The reason for a lock or unlock, which must be there and must not be blank.
+    pub fn reason(&self) -> Result<&str, TransactionError> {
+        match self.reason.as_deref().map(str::trim) {
+            Some(reason) if !reason.is_empty() => Ok(reason),
+            _ => Err(TransactionNeedsReason),
+        }
     }
+
+    /// Whether only administrators may submit the transaction.
+    pub fn is_admin(&self) -> bool {
+        matches!(self.r#type, TransactionType::Lock | TransactionType::Unlock)
+    }
+
+    /// Reads a transaction from a JSON object such as
+    /// `{"type": "deposit", "client": 1, "tx": 1, "amount": "1.5"}`. The amount may also be a
+    /// number, and may be left out or `null` for transactions that don't need one.
+    pub fn from_json(s: &str) -> serde_json::Result<Self> {
+        serde_json::from_str(s)
+    }
+}
+
+/// Where a stored transaction is in the dispute lifecycle.
+///
+/// ```text
+/// Processed -> Disputed -> Resolved
+///                       -> ChargedBack
+/// ```
+///
+/// Whether a `Resolved` transaction may move back to `Disputed` is decided by
+/// [`Policy`](crate::process::Policy). `ChargedBack` is final.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransactionStatus {
+    Processed,
+    Disputed,
+    Resolved,
+    ChargedBack,
+}
+
+impl fmt::Display for TransactionStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            TransactionStatus::Processed => "processed",
+            TransactionStatus::Disputed => "disputed",
+            TransactionStatus::Resolved => "resolved",
+            TransactionStatus::ChargedBack => "charged back",
+        };
+
+        f.write_str(s)
+    }
+}
+
+/// A deposit, withdrawal, conversion or transfer that has been applied to an account, kept around
+/// so that it can later be disputed.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct StoredTransaction {
+    pub r#type: TransactionType,
+    pub client: u16,
+    /// The amount deposited or withdrawn, or the amount a conversion took.
+    #[serde(with = "units")]
+    pub amount: Money,
+    #[serde(default, skip_serializing_if = "Currency::is_default")]
+    pub currency: Currency,
+    /// What a conversion gave in return.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub converted: Option<Converted>,
+    /// The client a transfer credited.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub to_client: Option<u16>,
+    pub status: TransactionStatus,
 }
 
-// serde requires a function for default values, can't use a literal
-const fn bool_false() -> bool {
-    false
+/// The amount a conversion credited, and its currency.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Converted {
+    #[serde(with = "units")]
+    pub amount: Money,
+    pub currency: Currency,
 }
 
 #[cfg(test)]
@@ -100,16 +291,81 @@ mod tests {
     use super::*;
 
     #[test]
-    fn test_money_string_to_u64() {
-        assert_eq!(money_string_to_u64("10".to_string()).unwrap(), 100000);
-        assert_eq!(money_string_to_u64("10.".to_string()).unwrap(), 100000);
-        assert_eq!(money_string_to_u64("10.1".to_string()).unwrap(), 101000);
-        assert_eq!(money_string_to_u64("10.01".to_string()).unwrap(), 100100);
-        assert_eq!(money_string_to_u64("10.001".to_string()).unwrap(), 100010);
-        assert_eq!(money_string_to_u64("10.0001".to_string()).unwrap(), 100001);
+    fn test_parse_amount() {
+        assert_eq!(parse_amount("10.0001"), Ok(Money::from_units(100001)));
         assert_eq!(
-            money_string_to_u64("10.00001".to_string()),
+            parse_amount("10.00001"),
             Err(TransactionAmountImproperlyFormatted)
         );
+        assert_eq!(
+            parse_amount("-1"),
+            Err(TransactionAmountImproperlyFormatted)
+        );
+        assert_eq!(
+            parse_amount("-0"),
+            Err(TransactionAmountImproperlyFormatted)
+        );
+        assert_eq!(parse_amount("922337203685477.5807"), Ok(Money::MAX));
+        assert_eq!(
+            parse_amount("922337203685477.5808"),
+            Err(TransactionAmountTooLarge)
+        );
+        assert_eq!(
+            parse_amount("99999999999999999999999"),
+            Err(TransactionAmountTooLarge)
+        );
+    }
+
+    #[test]
+    fn test_from_json() {
+        let t =
+            Transaction::from_json(r#"{"type":"deposit","client":1,"tx":2,"amount":1.5}"#).unwrap();
+        assert_eq!(t.amount, Some(Money::from_units(15000)));
+        let t =
+            Transaction::from_json(r#"{"type":"deposit","client":1,"tx":2,"amount":"10.0001"}"#)
+                .unwrap();
+        assert_eq!(t.amount, Some(Money::from_units(100001)));
+        let t = Transaction::from_json(r#"{"type":"dispute","client":1,"tx":2,"amount":null}"#)
+            .unwrap();
+        assert_eq!(t.amount, None);
+        let t = Transaction::from_json(r#"{"type":"dispute","client":1,"tx":2}"#).unwrap();
+        assert_eq!(t.amount, None);
+        assert_eq!(t.currency, Currency::DEFAULT);
+        let t = Transaction::from_json(
+            r#"{"type":"deposit","client":1,"tx":2,"amount":"1","currency":"eur"}"#,
+        )
+        .unwrap();
+        assert_eq!(t.currency.as_str(), "EUR");
+        let t = Transaction::from_json(
+            r#"{"type":"convert","client":1,"tx":2,"amount":"1","currency":"EUR","to_currency":"USD","timestamp":1700000000}"#,
+        )
+        .unwrap();
+        assert_eq!(t.to_currency(), Ok("USD".parse().unwrap()));
+        assert_eq!(t.timestamp, Some(1700000000));
+        let t = Transaction::from_json(
+            r#"{"type":"transfer","client":1,"tx":3,"amount":"1","to_client":2}"#,
+        )
+        .unwrap();
+        assert_eq!(t.to_client(), Ok(2));
+        let t =
+            Transaction::from_json(r#"{"type":"unlock","client":1,"tx":4,"reason":" REVIEWED "}"#)
+                .unwrap();
+        assert_eq!(t.reason(), Ok("REVIEWED"));
+
+        // the same precision rules as CSV apply, whether the amount is a string or a number
+        assert!(
+            Transaction::from_json(r#"{"type":"deposit","client":1,"tx":2,"amount":1.00001}"#)
+                .is_err()
+        );
+        assert!(Transaction::from_json(
+            r#"{"type":"deposit","client":1,"tx":2,"amount":"1.00001"}"#
+        )
+        .is_err());
+        assert!(
+            Transaction::from_json(r#"{"type":"deposit","client":1,"tx":2,"amount":1e3}"#).is_err()
+        );
+        assert!(
+            Transaction::from_json(r#"{"type":"deposit","client":1,"tx":2,"amount":-1}"#).is_err()
+        );
     }
 }
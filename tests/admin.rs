@@ -0,0 +1,168 @@
+use transactions::{
+    journal::{self, Journal, Operation},
+    process::{Policy, TransactionProcessingError},
+    State, TransactionError,
+};
+
+mod common;
+use common::process;
+
+fn admin() -> State {
+    State::with_policy(Policy {
+        audit: true,
+        admin: true,
+        ..Policy::default()
+    })
+}
+
+// client 1's account, locked by a chargeback
+const CHARGED_BACK: &str = "\
+type,client,tx,amount,reason
+deposit,1,1,10.0,
+deposit,1,2,5.0,
+dispute,1,2,,
+chargeback,1,2,,
+";
+
+#[test]
+fn admin_transactions_need_the_policy() {
+    let mut state = State::new();
+    process(CHARGED_BACK, &mut state);
+
+    assert_eq!(
+        process(
+            "type,client,tx,reason\n\
+             unlock,1,3,REVIEWED\n\
+             lock,2,4,FRAUD\n",
+            &mut state
+        ),
+        vec![
+            Err(TransactionProcessingError::AdminTransactionNotAllowed),
+            Err(TransactionProcessingError::AdminTransactionNotAllowed),
+        ]
+    );
+    assert!(state.accounts[&1].locked);
+    assert!(!state.accounts.contains_key(&2));
+}
+
+#[test]
+fn unlocking_reinstates_an_account() {
+    let mut state = admin();
+    process(CHARGED_BACK, &mut state);
+
+    let results = process(
+        "type,client,tx,amount,reason\n\
+         deposit,1,3,1.0,\n\
+         unlock,1,4,,REVIEWED\n\
+         deposit,1,3,1.0,\n\
+         withdrawal,1,5,11.0,\n",
+        &mut state,
+    );
+
+    assert_eq!(
+        results,
+        vec![
+            Err(TransactionProcessingError::AccountLocked),
+            Ok(()),
+            Ok(()),
+            Ok(())
+        ]
+    );
+    let account = &state.accounts[&1];
+    assert!(!account.locked);
+    assert!(account.balances.is_empty());
+}
+
+#[test]
+fn locking_freezes_an_account() {
+    let mut state = admin();
+    let results = process(
+        "type,client,tx,amount,reason\n\
+         deposit,1,1,10.0,\n\
+         lock,1,2,,\n\
+         lock,1,2,, \n\
+         lock,1,2,,SANCTIONS\n\
+         withdrawal,1,3,1.0,\n\
+         lock,1,4,,SANCTIONS\n",
+        &mut state,
+    );
+
+    let needs_reason = || {
+        Err(
+            TransactionProcessingError::TransactionProcessingTransactionError {
+                source: TransactionError::TransactionNeedsReason,
+            },
+        )
+    };
+    assert_eq!(
+        results,
+        vec![
+            Ok(()),
+            needs_reason(),
+            needs_reason(),
+            Ok(()),
+            Err(TransactionProcessingError::AccountLocked),
+            Err(TransactionProcessingError::AccountLocked),
+        ]
+    );
+    assert!(state.accounts[&1].locked);
+}
+
+#[test]
+fn unlocking_needs_a_locked_account() {
+    let mut state = admin();
+    let results = process(
+        "type,client,tx,amount,reason\n\
+         deposit,1,1,10.0,\n\
+         unlock,1,2,,REVIEWED\n\
+         unlock,2,3,,REVIEWED\n",
+        &mut state,
+    );
+
+    assert_eq!(
+        results[1..],
+        [
+            Err(TransactionProcessingError::AccountNotLocked),
+            Err(TransactionProcessingError::AccountNotLocked),
+        ]
+    );
+    assert!(!state.accounts.contains_key(&2));
+}
+
+#[test]
+fn journals_record_the_reason() {
+    let mut state = admin();
+    state.journal = Some(Journal::new());
+    process(CHARGED_BACK, &mut state);
+    process(
+        "type,client,tx,reason\n\
+         unlock,1,3,REVIEWED\n\
+         lock,2,4,SANCTIONS\n",
+        &mut state,
+    );
+
+    let entries = state.journal.as_ref().unwrap().entries();
+    let admin = entries
+        .iter()
+        .filter(|e| e.reason.is_some())
+        .map(|e| (e.client, e.operation, e.reason.as_deref().unwrap()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        admin,
+        vec![
+            (1, Operation::Unlock, "REVIEWED"),
+            (2, Operation::Lock, "SANCTIONS"),
+        ]
+    );
+
+    let mut buf = Vec::new();
+    for entry in entries {
+        serde_json::to_writer(&mut buf, entry).unwrap();
+        buf.push(b'\n');
+    }
+    let mut replayed = State::new();
+    journal::replay(buf.as_slice(), &mut replayed).unwrap();
+    assert_eq!(replayed.accounts, state.accounts);
+    assert!(!replayed.accounts[&1].locked);
+    assert!(replayed.accounts[&2].locked);
+}
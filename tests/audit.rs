@@ -0,0 +1,215 @@
+use std::collections::HashMap;
+
+use transactions::{
+    audit::{self, AuditError, Violation},
+    process::{self, Policy, TransactionProcessingError, WithdrawalDisputes},
+    store::{StoreError, TransactionStore},
+    Account, Currency, Money, State, StoredTransaction, TransactionStatus, TransactionType,
+};
+
+mod common;
+use common::{audited, transaction, Rng};
+
+#[test]
+fn correct_processing_passes() {
+    for withdrawal_disputes in [
+        WithdrawalDisputes::Disallow,
+        WithdrawalDisputes::CreditOnChargeback,
+        WithdrawalDisputes::Release,
+    ] {
+        let mut rng = Rng(0x2545F4914F6CDD1D);
+        let mut state = State::with_policy(Policy {
+            withdrawal_disputes,
+            audit: true,
+            ..Policy::default()
+        });
+
+        for tx in 0..10000 {
+            let client = (rng.next() % 4) as u16;
+            let earlier = (rng.next() % (tx as u64 + 1)) as u32;
+            let t = match rng.next() % 5 {
+                0 => transaction(
+                    TransactionType::Deposit,
+                    client,
+                    tx,
+                    Some((rng.next() % 1000000) as i64),
+                ),
+                1 => transaction(
+                    TransactionType::Withdrawal,
+                    client,
+                    tx,
+                    Some((rng.next() % 1000000) as i64),
+                ),
+                2 => transaction(TransactionType::Dispute, client, earlier, None),
+                3 => transaction(TransactionType::Resolve, client, earlier, None),
+                _ => transaction(TransactionType::Chargeback, client, earlier, None),
+            };
+            if let Err(e) = process::process_one(&mut state, t) {
+                assert_ne!(e.kind(), "InvariantViolated", "{}", e);
+            }
+        }
+
+        audit::check(&state).unwrap();
+    }
+}
+
+#[test]
+fn reports_total_drift() {
+    let mut state = audited();
+    process::process_one(
+        &mut state,
+        transaction(TransactionType::Deposit, 1, 1, Some(10000)),
+    )
+    .unwrap();
+    let balances = &mut state.accounts.get_mut(&1).unwrap().balances;
+    balances.get_mut(&Currency::DEFAULT).unwrap().total = Money::from_units(10001);
+
+    let broken = Violation::Total {
+        client: 1,
+        currency: Currency::DEFAULT,
+        available: Money::from_units(15000),
+        held: Money::from_units(0),
+        total: Money::from_units(15001),
+    };
+    assert_eq!(
+        process::process_one(
+            &mut state,
+            transaction(TransactionType::Deposit, 1, 2, Some(5000)),
+        ),
+        Err(TransactionProcessingError::InvariantViolated {
+            source: broken.clone()
+        })
+    );
+    assert!(matches!(audit::check(&state), Err(AuditError::Violation(v)) if v == broken));
+}
+
+// a broken store that never records a dispute
+struct ForgetfulStore(HashMap<u32, StoredTransaction>);
+
+impl TransactionStore for ForgetfulStore {
+    fn get(&self, id: u32) -> Result<Option<StoredTransaction>, StoreError> {
+        TransactionStore::get(&self.0, id)
+    }
+
+    fn put(&mut self, id: u32, t: StoredTransaction) -> Result<(), StoreError> {
+        if t.status == TransactionStatus::Disputed {
+            return Ok(());
+        }
+        TransactionStore::put(&mut self.0, id, t)
+    }
+
+    fn remove(&mut self, id: u32) -> Result<(), StoreError> {
+        TransactionStore::remove(&mut self.0, id)
+    }
+
+    fn entries(
+        &self,
+    ) -> Box<dyn Iterator<Item = Result<(u32, StoredTransaction), StoreError>> + '_> {
+        self.0.entries()
+    }
+}
+
+#[test]
+fn reports_held_drift() {
+    let mut state = State::with_store(
+        ForgetfulStore(HashMap::new()),
+        Policy {
+            audit: true,
+            ..Policy::default()
+        },
+    );
+    process::process_one(
+        &mut state,
+        transaction(TransactionType::Deposit, 1, 1, Some(10000)),
+    )
+    .unwrap();
+
+    assert_eq!(
+        process::process_one(
+            &mut state,
+            transaction(TransactionType::Dispute, 1, 1, None)
+        ),
+        Err(TransactionProcessingError::InvariantViolated {
+            source: Violation::Held {
+                client: 1,
+                currency: Currency::DEFAULT,
+                held: Money::from_units(10000),
+                expected: Money::ZERO,
+            }
+        })
+    );
+}
+
+#[test]
+fn check_finds_drift_from_before() {
+    let mut state = audited();
+    process::process_one(
+        &mut state,
+        transaction(TransactionType::Deposit, 1, 1, Some(10000)),
+    )
+    .unwrap();
+    // held and available both moved, so the total still adds up
+    let balances = &mut state.accounts.get_mut(&1).unwrap().balances;
+    let balance = balances.get_mut(&Currency::DEFAULT).unwrap();
+    balance.available = Money::from_units(9900);
+    balance.held = Money::from_units(100);
+
+    assert!(matches!(
+        audit::check(&state),
+        Err(AuditError::Violation(Violation::Held { client: 1, held, expected, .. }))
+            if held == Money::from_units(100) && expected == Money::ZERO
+    ));
+}
+
+#[test]
+fn check_adds_up_held_in_any_order() {
+    use TransactionType::*;
+
+    // two of the disputed deposits alone call for more than a balance can hold, but the disputed
+    // withdrawal between them brings it back. The store hands them to `check` in a different order
+    // each time, so try a few.
+    for _ in 0..32 {
+        let mut state = audited();
+        for t in [
+            transaction(Deposit, 2, 1, Some(i64::MAX)),
+            transaction(Withdrawal, 2, 2, Some(i64::MAX)),
+            transaction(Deposit, 2, 3, Some(i64::MAX)),
+            transaction(Dispute, 2, 1, None),
+            transaction(Dispute, 2, 2, None),
+            transaction(Dispute, 2, 3, None),
+        ] {
+            process::process_one(&mut state, t).unwrap();
+        }
+
+        assert_eq!(audit::check(&state).ok(), Some(()));
+    }
+}
+
+#[test]
+fn reports_transactions_without_an_account() {
+    let mut state = audited();
+    state.transactions.insert(
+        7,
+        StoredTransaction {
+            r#type: TransactionType::Deposit,
+            client: 3,
+            amount: Money::from_units(10000),
+            currency: Currency::DEFAULT,
+            converted: None,
+            to_client: None,
+            status: TransactionStatus::Processed,
+        },
+    );
+
+    assert!(matches!(
+        audit::check(&state),
+        Err(AuditError::Violation(Violation::NoAccount {
+            tx: 7,
+            client: 3
+        }))
+    ));
+
+    // a fresh account for client 3 is fine, until something else touches transaction 7
+    state.accounts.insert(3, Account::new(3));
+    audit::check(&state).unwrap();
+}
@@ -0,0 +1,120 @@
+use transactions::{
+    batch::{self, BatchError},
+    process::{self, Policy, TransactionProcessingError},
+    store::{AccountStore, FileAccountStore, FileTransactionStore, TransactionStore},
+    Account, Currency, Money, State, Transaction, TransactionType,
+};
+
+fn deposit(client: u16, tx: u32, amount: i64) -> Transaction {
+    Transaction::new(
+        TransactionType::Deposit,
+        client,
+        tx,
+        Some(Money::from_units(amount)),
+    )
+}
+
+fn dispute(client: u16, tx: u32) -> Transaction {
+    Transaction::new(TransactionType::Dispute, client, tx, None)
+}
+
+fn withdrawal(client: u16, tx: u32, amount: i64) -> Transaction {
+    Transaction::new(
+        TransactionType::Withdrawal,
+        client,
+        tx,
+        Some(Money::from_units(amount)),
+    )
+}
+
+#[test]
+fn batch_applies_every_transaction() {
+    let mut state = State::new();
+    batch::process_batch(
+        &mut state,
+        vec![deposit(1, 1, 20000), withdrawal(1, 2, 5000), dispute(1, 1)],
+    )
+    .unwrap();
+
+    let mut expected = Account::new(1);
+    let usd = Currency::DEFAULT;
+    expected.deposit(usd, Money::from_units(20000)).unwrap();
+    expected.withdraw(usd, Money::from_units(5000)).unwrap();
+    expected.hold(usd, Money::from_units(20000)).unwrap();
+    assert_eq!(state.accounts.get(&1), Some(&expected));
+    assert_eq!(state.transactions.len(), 2);
+}
+
+#[test]
+fn failed_batch_rolls_back() {
+    let mut state = State::new();
+    process::process_one(&mut state, deposit(1, 1, 10000)).unwrap();
+    let accounts = state.accounts.clone();
+    let transactions = state.transactions.clone();
+
+    // touches an existing account and stored transaction, creates a new account and stores new
+    // transactions, all before failing
+    let result = batch::process_batch(
+        &mut state,
+        vec![
+            dispute(1, 1),
+            deposit(2, 2, 30000),
+            withdrawal(2, 3, 10000),
+            deposit(2, 4, 10000),
+            withdrawal(1, 5, 10000),
+        ],
+    );
+
+    assert_eq!(
+        result,
+        Err(BatchError::TransactionFailed {
+            index: 4,
+            source: TransactionProcessingError::TransactionProcessingAccountError {
+                source: transactions::account::AccountError::NotEnoughAvailable,
+            },
+        })
+    );
+    assert_eq!(state.accounts, accounts);
+    assert_eq!(state.transactions, transactions);
+}
+
+#[test]
+fn failed_batch_rolls_back_file_stores() {
+    let dir = std::env::temp_dir().join(format!("transactions-test-batch-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let open = || {
+        State::with_stores(
+            FileTransactionStore::open(dir.join("transactions.db")).unwrap(),
+            FileAccountStore::open(dir.join("accounts.log")).unwrap(),
+            Policy::default(),
+        )
+    };
+
+    let mut state = open();
+    process::process_one(&mut state, deposit(1, 1, 10000)).unwrap();
+    let result = batch::process_batch(
+        &mut state,
+        vec![deposit(1, 2, 10000), deposit(3, 3, 10000), dispute(3, 1)],
+    );
+    assert!(matches!(
+        result,
+        Err(BatchError::TransactionFailed { index: 2, .. })
+    ));
+    drop(state);
+
+    // the rollback was persisted, not just applied in memory
+    let state = open();
+    let mut expected = Account::new(1);
+    expected
+        .deposit(Currency::DEFAULT, Money::from_units(10000))
+        .unwrap();
+    assert_eq!(
+        state.accounts.all().cloned().collect::<Vec<_>>(),
+        vec![expected]
+    );
+    assert_eq!(state.transactions.get(2).unwrap(), None);
+    assert_eq!(state.transactions.get(3).unwrap(), None);
+    assert!(state.transactions.get(1).unwrap().is_some());
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
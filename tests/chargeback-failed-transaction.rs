@@ -1,7 +1,7 @@
 use transactions::{
     account::AccountError,
     process::{self, TransactionProcessingError},
-    Account, State, Transaction, TransactionType,
+    Account, Balance, Money, State, Transaction, TransactionType,
 };
 
 #[test]
@@ -10,13 +10,12 @@ fn chargeback_failed_transaction() {
 
     process::process_one(
         &mut state,
-        Transaction {
-            r#type: TransactionType::Deposit,
-            amount: Some(100000),
-            client: 1,
-            tx: 1,
-            disputed: false,
-        },
+        Transaction::new(
+            TransactionType::Deposit,
+            1,
+            1,
+            Some(Money::from_units(100000)),
+        ),
     )
     .unwrap();
 
@@ -24,13 +23,12 @@ fn chargeback_failed_transaction() {
     assert_eq!(
         process::process_one(
             &mut state,
-            Transaction {
-                r#type: TransactionType::Withdrawal,
-                amount: Some(1000000),
-                client: 1,
-                tx: 2,
-                disputed: false,
-            },
+            Transaction::new(
+                TransactionType::Withdrawal,
+                1,
+                2,
+                Some(Money::from_units(1000000))
+            ),
         ),
         Err(
             TransactionProcessingError::TransactionProcessingAccountError {
@@ -43,25 +41,21 @@ fn chargeback_failed_transaction() {
     assert_eq!(
         process::process_one(
             &mut state,
-            Transaction {
-                r#type: TransactionType::Dispute,
-                amount: None,
-                client: 1,
-                tx: 2,
-                disputed: false,
-            },
+            Transaction::new(TransactionType::Dispute, 1, 2, None),
         ),
         Err(TransactionProcessingError::TransactionDoesNotExist)
     );
 
     assert_eq!(
         state.accounts.get(&1).unwrap(),
-        &Account {
-            id: 1,
-            available: 100000,
-            held: 0,
-            total: 100000,
-            locked: false
-        }
+        &Account::with_balance(
+            1,
+            Balance {
+                available: Money::from_units(100000),
+                held: Money::from_units(0),
+                total: Money::from_units(100000)
+            },
+            false
+        )
     );
 }
@@ -1,4 +1,4 @@
-use transactions::{process, Account, State, Transaction, TransactionType};
+use transactions::{process, Account, Balance, Money, State, Transaction, TransactionType};
 
 #[test]
 fn chargeback_deposit() {
@@ -6,47 +6,36 @@ fn chargeback_deposit() {
 
     process::process_one(
         &mut state,
-        Transaction {
-            r#type: TransactionType::Deposit,
-            amount: Some(100000),
-            client: 1,
-            tx: 1,
-            disputed: false,
-        },
+        Transaction::new(
+            TransactionType::Deposit,
+            1,
+            1,
+            Some(Money::from_units(100000)),
+        ),
     )
     .unwrap();
     process::process_one(
         &mut state,
-        Transaction {
-            r#type: TransactionType::Dispute,
-            amount: None,
-            client: 1,
-            tx: 1,
-            disputed: false,
-        },
+        Transaction::new(TransactionType::Dispute, 1, 1, None),
     )
     .unwrap();
     process::process_one(
         &mut state,
-        Transaction {
-            r#type: TransactionType::Chargeback,
-            amount: None,
-            client: 1,
-            tx: 1,
-            disputed: false,
-        },
+        Transaction::new(TransactionType::Chargeback, 1, 1, None),
     )
     .unwrap();
 
     assert_eq!(
         state.accounts.get(&1).unwrap(),
-        &Account {
-            id: 1,
-            available: 0,
-            held: 0,
-            total: 0,
-            locked: true
-        }
+        &Account::with_balance(
+            1,
+            Balance {
+                available: Money::from_units(0),
+                held: Money::from_units(0),
+                total: Money::from_units(0)
+            },
+            true
+        )
     );
 }
 
@@ -56,57 +45,45 @@ fn chargeback_withdrawal() {
 
     process::process_one(
         &mut state,
-        Transaction {
-            r#type: TransactionType::Deposit,
-            amount: Some(100000),
-            client: 1,
-            tx: 1,
-            disputed: false,
-        },
+        Transaction::new(
+            TransactionType::Deposit,
+            1,
+            1,
+            Some(Money::from_units(100000)),
+        ),
     )
     .unwrap();
     process::process_one(
         &mut state,
-        Transaction {
-            r#type: TransactionType::Withdrawal,
-            amount: Some(10000),
-            client: 1,
-            tx: 2,
-            disputed: false,
-        },
+        Transaction::new(
+            TransactionType::Withdrawal,
+            1,
+            2,
+            Some(Money::from_units(10000)),
+        ),
     )
     .unwrap();
     process::process_one(
         &mut state,
-        Transaction {
-            r#type: TransactionType::Dispute,
-            amount: None,
-            client: 1,
-            tx: 2,
-            disputed: false,
-        },
+        Transaction::new(TransactionType::Dispute, 1, 2, None),
     )
     .unwrap();
     process::process_one(
         &mut state,
-        Transaction {
-            r#type: TransactionType::Chargeback,
-            amount: None,
-            client: 1,
-            tx: 2,
-            disputed: false,
-        },
+        Transaction::new(TransactionType::Chargeback, 1, 2, None),
     )
     .unwrap();
 
     assert_eq!(
         state.accounts.get(&1).unwrap(),
-        &Account {
-            id: 1,
-            available: 100000,
-            held: 0,
-            total: 100000,
-            locked: true
-        }
+        &Account::with_balance(
+            1,
+            Balance {
+                available: Money::from_units(100000),
+                held: Money::from_units(0),
+                total: Money::from_units(100000)
+            },
+            true
+        )
     );
 }
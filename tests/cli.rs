@@ -0,0 +1,341 @@
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::process::{Command, Output, Stdio};
+
+const INPUT: &str = "type,client,tx,amount\n\
+                     deposit,1,1,2.0\n\
+                     deposit,2,2,1.0\n\
+                     dispute,1,1,\n";
+const BAD_INPUT: &str = "type,client,tx,amount\n\
+                         deposit,1,1,2.0\n\
+                         resolve,1,1,\n\
+                         withdrawal,1,2,1.00001\n";
+
+fn run(args: &[&str], stdin: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_transactions"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    // a run that fails before reading its input may close stdin before it has all been written
+    match child.stdin.take().unwrap().write_all(stdin.as_bytes()) {
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => {}
+        result => result.unwrap(),
+    }
+
+    child.wait_with_output().unwrap()
+}
+
+fn stdout(output: &Output) -> &str {
+    std::str::from_utf8(&output.stdout).unwrap()
+}
+
+fn stderr(output: &Output) -> &str {
+    std::str::from_utf8(&output.stderr).unwrap()
+}
+
+fn temp_path(name: &str) -> PathBuf {
+    env::temp_dir().join(format!("transactions-cli-{}-{}", std::process::id(), name))
+}
+
+#[test]
+fn reads_stdin_with_and_without_subcommand() {
+    let expected = "client,available,held,total,locked\n\
+                    1,0.0000,2.0000,2.0000,false\n\
+                    2,1.0000,0.0000,1.0000,false\n";
+
+    for args in [&["process", "-"][..], &["-"][..]] {
+        let output = run(args, INPUT);
+        assert!(output.status.success(), "{}", stderr(&output));
+        assert_eq!(stdout(&output), expected);
+    }
+}
+
+#[test]
+fn bad_rows() {
+    let skipped = run(&["process", "-"], BAD_INPUT);
+    assert_eq!(skipped.status.code(), Some(0));
+    assert_eq!(stderr(&skipped), "");
+
+    let warned = run(&["process", "-", "--bad-rows", "warn"], BAD_INPUT);
+    assert_eq!(warned.status.code(), Some(0));
+    assert_eq!(stdout(&warned), stdout(&skipped));
+    let lines = stderr(&warned).lines().collect::<Vec<_>>();
+    assert_eq!(lines.len(), 2);
+    assert!(lines[0].starts_with("<stdin>:3: IllegalTransition: "));
+    assert!(lines[1].starts_with("<stdin>:4: InvalidRecord: "));
+
+    let failed = run(&["process", "-", "--bad-rows", "fail"], BAD_INPUT);
+    assert_eq!(failed.status.code(), Some(3));
+    assert_eq!(stdout(&failed), stdout(&skipped));
+}
+
+#[test]
+fn validate() {
+    let valid = run(&["validate", "-"], INPUT);
+    assert_eq!(valid.status.code(), Some(0));
+    assert_eq!(stdout(&valid), "");
+
+    let invalid = run(&["validate", "-"], BAD_INPUT);
+    assert_eq!(invalid.status.code(), Some(3));
+    assert_eq!(stdout(&invalid), "");
+    assert!(stderr(&invalid).contains("rejected 2 rows"));
+}
+
+#[test]
+fn replay_and_report() {
+    let snapshot = temp_path("snapshot.json");
+    let snapshot = snapshot.to_str().unwrap();
+    let first = temp_path("first.csv");
+    fs::write(&first, "type,client,tx,amount\ndeposit,1,1,2.0\n").unwrap();
+
+    let output = run(
+        &["process", first.to_str().unwrap(), "--snapshot", snapshot],
+        "",
+    );
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    // the dispute refers to a deposit that only exists in the snapshot
+    let replayed = run(
+        &[
+            "replay",
+            "--from-snapshot",
+            snapshot,
+            "-",
+            "--snapshot",
+            snapshot,
+        ],
+        "type,client,tx,amount\ndispute,1,1,\n",
+    );
+    assert!(replayed.status.success(), "{}", stderr(&replayed));
+    assert_eq!(
+        stdout(&replayed),
+        "client,available,held,total,locked\n1,0.0000,2.0000,2.0000,false\n"
+    );
+
+    let reported = run(&["report", "--from-snapshot", snapshot], "");
+    assert!(reported.status.success(), "{}", stderr(&reported));
+    assert_eq!(stdout(&reported), stdout(&replayed));
+
+    fs::remove_file(snapshot).unwrap();
+    fs::remove_file(first).unwrap();
+}
+
+#[test]
+fn fatal_errors_exit_non_zero() {
+    let missing = run(&["process", "does-not-exist.csv"], "");
+    assert_eq!(missing.status.code(), Some(1));
+    assert!(stderr(&missing).starts_with("error: does-not-exist.csv: "));
+
+    // so does running out of space, whether for the balances or a snapshot
+    for flag in ["--output", "--snapshot"] {
+        let full = run(&["process", "-", flag, "/dev/full"], INPUT);
+        assert_eq!(full.status.code(), Some(1), "{}", flag);
+        assert!(
+            stderr(&full).contains("No space left on device"),
+            "{}",
+            flag
+        );
+    }
+
+    let usage = run(&["report"], "");
+    assert_eq!(usage.status.code(), Some(2));
+}
+
+#[test]
+fn strict_aborts_without_balances() {
+    let snapshot = temp_path("strict-snapshot.json");
+    let snapshot = snapshot.to_str().unwrap();
+
+    for args in [
+        &["process", "-", "--strict", "--snapshot", snapshot][..],
+        &["process", "-", "--bad-rows", "abort", "--workers", "2"][..],
+    ] {
+        let output = run(args, BAD_INPUT);
+        assert_eq!(output.status.code(), Some(3));
+        assert_eq!(stdout(&output), "");
+        assert_eq!(
+            stderr(&output),
+            "error: aborted at <stdin>:3: IllegalTransition: \
+             transaction cannot go from processed to resolved\n  \
+             record: resolve,1,1,\n"
+        );
+    }
+    assert!(fs::metadata(snapshot).is_err());
+
+    let output = run(&["process", "-", "--strict"], INPUT);
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    // a state directory is left as it was before the aborted run
+    let state_dir = temp_path("strict-state");
+    let dir = state_dir.to_str().unwrap();
+    let before = run(&["process", "-", "--state-dir", dir], INPUT);
+    assert!(before.status.success(), "{}", stderr(&before));
+    let output = run(&["process", "-", "--strict", "--state-dir", dir], BAD_INPUT);
+    assert_eq!(output.status.code(), Some(3));
+    assert_eq!(stdout(&output), "");
+    let after = run(&["report", "--state-dir", dir], "");
+    assert_eq!(stdout(&after), stdout(&before));
+
+    fs::remove_dir_all(state_dir).unwrap();
+}
+
+#[test]
+fn report_from_journal() {
+    let journal = temp_path("journal.jsonl");
+    let journal = journal.to_str().unwrap();
+
+    let processed = run(&["process", "-", "--journal", journal], INPUT);
+    assert!(processed.status.success(), "{}", stderr(&processed));
+
+    let reported = run(&["report", "--from-journal", journal], "");
+    assert!(reported.status.success(), "{}", stderr(&reported));
+    assert_eq!(stdout(&reported), stdout(&processed));
+
+    fs::remove_file(journal).unwrap();
+}
+
+#[test]
+fn statement() {
+    let output = run(&["statement", "-", "--client", "1"], INPUT);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        stdout(&output),
+        "client,tx,type,amount,available,held,total,locked\n\
+         1,1,deposit,2.0000,2.0000,0.0000,2.0000,false\n\
+         1,1,dispute,2.0000,0.0000,2.0000,2.0000,false\n"
+    );
+}
+
+#[test]
+fn audit_rejects_a_broken_snapshot() {
+    let snapshot = temp_path("audit-snapshot.json");
+    fs::write(
+        &snapshot,
+        r#"{"version":1,"accounts":[{"client":1,"available":10000,"held":0,"total":20000,"locked":false}],"transactions":[]}"#,
+    )
+    .unwrap();
+    let snapshot = snapshot.to_str().unwrap();
+
+    let unaudited = run(&["process", "-", "--from-snapshot", snapshot], INPUT);
+    assert!(unaudited.status.success(), "{}", stderr(&unaudited));
+
+    let audited = run(
+        &["process", "-", "--from-snapshot", snapshot, "--audit"],
+        INPUT,
+    );
+    assert_eq!(audited.status.code(), Some(1));
+    assert!(stdout(&audited).is_empty());
+    assert!(
+        stderr(&audited)
+            .contains("client 1 has 1.0000 available and 0.0000 held but a total of 2.0000"),
+        "{}",
+        stderr(&audited)
+    );
+
+    fs::remove_file(snapshot).unwrap();
+}
+
+#[test]
+fn admin_rows_need_admin() {
+    let input = "type,client,tx,amount,reason\n\
+                 deposit,1,1,2.0,\n\
+                 lock,1,2,,SANCTIONS\n";
+
+    let rejected = run(&["process", "-", "--bad-rows", "warn"], input);
+    assert!(rejected.status.success(), "{}", stderr(&rejected));
+    assert!(stderr(&rejected).starts_with("<stdin>:3: AdminTransactionNotAllowed: "));
+    assert!(stdout(&rejected).ends_with("1,2.0000,0.0000,2.0000,false\n"));
+
+    let admin = run(&["process", "-", "--admin"], input);
+    assert!(admin.status.success(), "{}", stderr(&admin));
+    assert!(stdout(&admin).ends_with("1,2.0000,0.0000,2.0000,true\n"));
+}
+
+#[test]
+fn locked_accounts_accept_the_listed_types() {
+    let input = "type,client,tx,amount,reason\n\
+                 deposit,1,1,2.0,\n\
+                 lock,1,2,,SANCTIONS\n\
+                 deposit,1,3,1.0,\n";
+
+    let output = run(
+        &["process", "-", "--admin", "--locked-accounts", "deposit"],
+        input,
+    );
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).ends_with("1,3.0000,0.0000,3.0000,true\n"));
+
+    let unknown = run(&["process", "-", "--locked-accounts", "deposit,lock"], "");
+    assert!(!unknown.status.success());
+    assert!(stderr(&unknown).contains("unknown transaction type lock"));
+}
+
+#[test]
+fn workers_free_the_ids_of_failed_transactions() {
+    let input = "type,client,tx,amount\n\
+                 deposit,1,1,5\n\
+                 deposit,1,2,3\n\
+                 withdrawal,1,3,100\n\
+                 deposit,1,3,1\n";
+
+    let sequential = run(&["process", "-"], input);
+    let parallel = run(&["process", "-", "--workers", "2"], input);
+    assert!(parallel.status.success(), "{}", stderr(&parallel));
+    assert_eq!(stdout(&parallel), stdout(&sequential));
+    assert!(stdout(&sequential).ends_with("1,9.0000,0.0000,9.0000,false\n"));
+}
+
+#[test]
+fn report_needs_an_existing_state_dir() {
+    let state_dir = temp_path("missing-state");
+    let dir = state_dir.to_str().unwrap();
+
+    let output = run(&["report", "--state-dir", dir], "");
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).contains("transactions.db: No such file"));
+    assert!(fs::metadata(&state_dir).is_err());
+
+    // nor is a missing file created in a directory that does exist
+    fs::create_dir(&state_dir).unwrap();
+    fs::write(state_dir.join("transactions.db"), "").unwrap();
+    let output = run(&["report", "--state-dir", dir], "");
+    assert_eq!(output.status.code(), Some(1));
+    assert!(fs::metadata(state_dir.join("accounts.log")).is_err());
+
+    fs::remove_dir_all(state_dir).unwrap();
+}
+
+#[test]
+fn aborted_runs_leave_the_journal_alone() {
+    let journal = temp_path("aborted-journal.jsonl");
+    let path = journal.to_str().unwrap();
+    let processed = run(&["process", "-", "--journal", path], INPUT);
+    assert!(processed.status.success(), "{}", stderr(&processed));
+    let before = fs::read_to_string(&journal).unwrap();
+
+    let process = ["process", "-", "--strict", "--journal", path];
+    let replay = [
+        "replay",
+        "-",
+        "--strict",
+        "--from-journal",
+        path,
+        "--journal",
+        path,
+    ];
+    for args in [&process[..], &replay[..]] {
+        let output = run(args, BAD_INPUT);
+        assert_eq!(output.status.code(), Some(3));
+        assert_eq!(fs::read_to_string(&journal).unwrap(), before);
+    }
+
+    let reported = run(&["report", "--from-journal", path], "");
+    assert_eq!(stdout(&reported), stdout(&processed));
+
+    fs::remove_file(journal).unwrap();
+}
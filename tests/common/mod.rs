@@ -0,0 +1,45 @@
+// helpers shared by the test crates; not every crate uses all of them
+#![allow(dead_code)]
+
+use std::io::Cursor;
+
+use transactions::{
+    input::{self, Format},
+    process::{self, Policy, TransactionProcessingError},
+    Money, State, Transaction, TransactionType,
+};
+
+// small xorshift generator so that the workload is the same on every run
+pub struct Rng(pub u64);
+
+impl Rng {
+    pub fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}
+
+pub fn transaction(
+    r#type: TransactionType,
+    client: u16,
+    tx: u32,
+    amount: Option<i64>,
+) -> Transaction {
+    Transaction::new(r#type, client, tx, amount.map(Money::from_units))
+}
+
+pub fn audited() -> State {
+    State::with_policy(Policy {
+        audit: true,
+        ..Policy::default()
+    })
+}
+
+pub fn process(input: &str, state: &mut State) -> Vec<Result<(), TransactionProcessingError>> {
+    input::read(Cursor::new(input.as_bytes().to_vec()), Format::Csv)
+        .unwrap()
+        .map(|row| process::process_one(state, row.unwrap().1))
+        .collect()
+}
@@ -0,0 +1,219 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use transactions::{
+    account::AccountError,
+    audit,
+    journal::{self, Journal},
+    process::{Policy, TransactionProcessingError},
+    rates::{FileRates, Rate},
+    snapshot::Snapshot,
+    Balance, Converted, Currency, Money, State, TransactionError,
+};
+
+mod common;
+use common::process;
+
+fn eur() -> Currency {
+    "EUR".parse().unwrap()
+}
+
+fn usd() -> Currency {
+    "USD".parse().unwrap()
+}
+
+// an audited state that converts euros to dollars at 1.1
+fn state() -> State {
+    let mut rates = HashMap::new();
+    rates.insert((eur(), usd()), "1.1".parse::<Rate>().unwrap());
+    let mut state = State::with_policy(Policy {
+        audit: true,
+        ..Policy::default()
+    });
+    state.rates = Some(Arc::new(rates));
+
+    state
+}
+
+fn balance(available: i64, held: i64) -> Balance {
+    Balance {
+        available: Money::from_units(available),
+        held: Money::from_units(held),
+        total: Money::from_units(available + held),
+    }
+}
+
+const CONVERT: &str = "\
+type,client,tx,amount,currency,to_currency
+deposit,1,1,10.0,EUR,
+convert,1,2,4.0,EUR,USD
+";
+
+#[test]
+fn converts_at_the_rate() {
+    let mut state = state();
+    assert_eq!(process(CONVERT, &mut state), vec![Ok(()), Ok(())]);
+
+    let account = &state.accounts[&1];
+    assert_eq!(account.balance(eur()), balance(60000, 0));
+    assert_eq!(account.balance(usd()), balance(44000, 0));
+    assert_eq!(
+        state.transactions[&2].converted,
+        Some(Converted {
+            amount: Money::from_units(44000),
+            currency: usd(),
+        })
+    );
+}
+
+#[test]
+fn failed_conversions_change_nothing() {
+    let mut state = state();
+    let results = process(
+        "type,client,tx,amount,currency,to_currency\n\
+         deposit,1,1,10.0,EUR,\n\
+         convert,1,2,11.0,EUR,USD\n\
+         convert,1,3,1.0,USD,EUR\n\
+         convert,1,4,1.0,EUR,\n\
+         convert,1,5,1.0,EUR,eur\n",
+        &mut state,
+    );
+
+    assert_eq!(
+        results,
+        vec![
+            Ok(()),
+            Err(
+                TransactionProcessingError::TransactionProcessingAccountError {
+                    source: AccountError::NotEnoughAvailable
+                }
+            ),
+            Err(TransactionProcessingError::RateUnavailable {
+                from: usd(),
+                to: eur(),
+            }),
+            Err(
+                TransactionProcessingError::TransactionProcessingTransactionError {
+                    source: TransactionError::TransactionNeedsTargetCurrency
+                }
+            ),
+            Err(
+                TransactionProcessingError::TransactionProcessingTransactionError {
+                    source: TransactionError::TransactionConvertsToSameCurrency
+                }
+            ),
+        ]
+    );
+    let account = &state.accounts[&1];
+    assert_eq!(account.balance(eur()), balance(100000, 0));
+    assert!(!account.balances.contains_key(&usd()));
+    assert_eq!(state.transactions.len(), 1);
+}
+
+#[test]
+fn conversions_need_rates() {
+    let mut state = State::new();
+    assert_eq!(
+        process(CONVERT, &mut state)[1],
+        Err(TransactionProcessingError::RateUnavailable {
+            from: eur(),
+            to: usd(),
+        })
+    );
+}
+
+#[test]
+fn disputes_hold_what_was_converted() {
+    let mut state = state();
+    process(CONVERT, &mut state);
+
+    process("type,client,tx\ndispute,1,2\n", &mut state);
+    let account = &state.accounts[&1];
+    assert_eq!(account.balance(eur()), balance(60000, 0));
+    assert_eq!(account.balance(usd()), balance(0, 44000));
+
+    process("type,client,tx\nresolve,1,2\n", &mut state);
+    assert_eq!(state.accounts[&1].balance(usd()), balance(44000, 0));
+    audit::check(&state).unwrap();
+}
+
+#[test]
+fn chargebacks_undo_both_sides() {
+    let mut state = state();
+    process(CONVERT, &mut state);
+    let results = process(
+        "type,client,tx\n\
+         dispute,1,2\n\
+         chargeback,1,2\n",
+        &mut state,
+    );
+
+    assert_eq!(results, vec![Ok(()), Ok(())]);
+    let account = &state.accounts[&1];
+    assert!(account.locked);
+    assert_eq!(account.balance(eur()), balance(100000, 0));
+    assert!(!account.balances.contains_key(&usd()));
+    audit::check(&state).unwrap();
+}
+
+#[test]
+fn rates_follow_the_timestamp() {
+    let rates = FileRates::from_reader(
+        "timestamp,from,to,rate\n\
+         1000,EUR,USD,1.1\n\
+         2000,EUR,USD,1.2\n"
+            .as_bytes(),
+    )
+    .unwrap();
+    let mut state = State::new();
+    state.rates = Some(Arc::new(rates));
+
+    let results = process(
+        "type,client,tx,amount,currency,to_currency,timestamp\n\
+         deposit,1,1,10.0,EUR,,\n\
+         convert,1,2,1.0,EUR,USD,500\n\
+         convert,1,3,1.0,EUR,USD,1500\n\
+         convert,1,4,1.0,EUR,USD,2500\n\
+         convert,1,5,1.0,EUR,USD,\n",
+        &mut state,
+    );
+
+    assert_eq!(
+        results[1..3],
+        [
+            Err(TransactionProcessingError::RateUnavailable {
+                from: eur(),
+                to: usd(),
+            }),
+            Ok(()),
+        ]
+    );
+    assert_eq!(results[3..], [Ok(()), Ok(())]);
+    assert_eq!(state.accounts[&1].balance(usd()), balance(35000, 0));
+}
+
+#[test]
+fn snapshots_and_journals_keep_conversions() {
+    let mut state = state();
+    state.journal = Some(Journal::new());
+    process(CONVERT, &mut state);
+    process("type,client,tx\ndispute,1,2\n", &mut state);
+
+    let mut restored = State::new();
+    Snapshot::capture(&state)
+        .unwrap()
+        .restore(&mut restored)
+        .unwrap();
+    assert_eq!(restored.accounts, state.accounts);
+    assert_eq!(restored.transactions, state.transactions);
+
+    let mut buf = Vec::new();
+    for entry in state.journal.as_ref().unwrap().entries() {
+        serde_json::to_writer(&mut buf, entry).unwrap();
+        buf.push(b'\n');
+    }
+    let mut replayed = State::new();
+    journal::replay(buf.as_slice(), &mut replayed).unwrap();
+    assert_eq!(replayed.accounts, state.accounts);
+    assert_eq!(replayed.transactions, state.transactions);
+}
@@ -0,0 +1,130 @@
+use transactions::{
+    account::AccountError,
+    audit,
+    journal::{self, Journal},
+    output,
+    process::{Policy, TransactionProcessingError},
+    snapshot::Snapshot,
+    Balance, Currency, Money, State,
+};
+
+mod common;
+use common::process;
+
+const INPUT: &str = "\
+type,client,tx,amount,currency
+deposit,1,1,10.0,EUR
+deposit,1,2,5.0,USD
+deposit,1,3,1.0,
+withdrawal,1,4,6.0,USD
+dispute,1,1,,
+dispute,1,2,,EUR
+deposit,2,5,3.0,usd
+";
+
+fn balance(available: i64, held: i64) -> Balance {
+    Balance {
+        available: Money::from_units(available),
+        held: Money::from_units(held),
+        total: Money::from_units(available + held),
+    }
+}
+
+#[test]
+fn currencies_are_kept_apart() {
+    let mut state = State::with_policy(Policy {
+        audit: true,
+        ..Policy::default()
+    });
+    let results = process(INPUT, &mut state);
+
+    assert_eq!(
+        results,
+        vec![
+            Ok(()),
+            Ok(()),
+            Ok(()),
+            // there are euros to spare, but not dollars
+            Err(
+                TransactionProcessingError::TransactionProcessingAccountError {
+                    source: AccountError::NotEnoughAvailable
+                }
+            ),
+            Ok(()),
+            Err(TransactionProcessingError::TransactionCurrencyMismatch),
+            Ok(()),
+        ]
+    );
+
+    let eur = "EUR".parse().unwrap();
+    let usd = "USD".parse().unwrap();
+    let account = &state.accounts[&1];
+    // the dispute without a currency held the euros it referred to
+    assert_eq!(account.balance(eur), balance(0, 100000));
+    assert_eq!(account.balance(usd), balance(50000, 0));
+    assert_eq!(account.balance(Currency::DEFAULT), balance(10000, 0));
+    assert_eq!(state.accounts[&2].balance(usd), balance(30000, 0));
+    audit::check(&state).unwrap();
+}
+
+#[test]
+fn chargebacks_lock_every_currency() {
+    let mut state = State::new();
+    process(INPUT, &mut state);
+    let results = process(
+        "type,client,tx,amount,currency\n\
+         chargeback,1,1,,\n\
+         deposit,1,6,1.0,USD\n",
+        &mut state,
+    );
+
+    assert_eq!(
+        results,
+        vec![Ok(()), Err(TransactionProcessingError::AccountLocked)]
+    );
+    let account = &state.accounts[&1];
+    assert!(account.locked);
+    assert!(!account.balances.contains_key(&"EUR".parse().unwrap()));
+}
+
+#[test]
+fn output_has_a_row_per_currency() {
+    let mut state = State::new();
+    process(INPUT, &mut state);
+
+    let mut buf = Vec::new();
+    output::write_accounts(&mut buf, state.accounts.values(), output::Format::Csv).unwrap();
+    assert_eq!(
+        String::from_utf8(buf).unwrap(),
+        "client,currency,available,held,total,locked\n\
+         1,,1.0000,0.0000,1.0000,false\n\
+         1,EUR,0.0000,10.0000,10.0000,false\n\
+         1,USD,5.0000,0.0000,5.0000,false\n\
+         2,USD,3.0000,0.0000,3.0000,false\n"
+    );
+}
+
+#[test]
+fn snapshots_and_journals_keep_currencies() {
+    let mut state = State::new();
+    state.journal = Some(Journal::new());
+    process(INPUT, &mut state);
+
+    let mut restored = State::new();
+    Snapshot::capture(&state)
+        .unwrap()
+        .restore(&mut restored)
+        .unwrap();
+    assert_eq!(restored.accounts, state.accounts);
+    assert_eq!(restored.transactions, state.transactions);
+
+    let mut buf = Vec::new();
+    for entry in state.journal.as_ref().unwrap().entries() {
+        serde_json::to_writer(&mut buf, entry).unwrap();
+        buf.push(b'\n');
+    }
+    let mut replayed = State::new();
+    journal::replay(buf.as_slice(), &mut replayed).unwrap();
+    assert_eq!(replayed.accounts, state.accounts);
+    assert_eq!(replayed.transactions, state.transactions);
+}
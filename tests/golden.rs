@@ -0,0 +1,39 @@
+use std::fs;
+use std::process::Command;
+
+// runs the binary over every sample file and compares its output byte for byte with the expected
+// output, which only works because accounts are written in a fixed order
+#[test]
+fn sample_files_match_expected_output() {
+    let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/sample-files");
+    let mut checked = 0;
+
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().and_then(|e| e.to_str()) != Some("csv") {
+            continue;
+        }
+
+        let expected = path
+            .parent()
+            .unwrap()
+            .join("expected")
+            .join(path.file_name().unwrap());
+        let output = Command::new(env!("CARGO_BIN_EXE_transactions"))
+            .arg(&path)
+            .output()
+            .unwrap();
+
+        assert!(output.status.success(), "{} failed", path.display());
+        assert_eq!(
+            String::from_utf8(output.stdout).unwrap(),
+            fs::read_to_string(&expected).unwrap(),
+            "{} does not match {}",
+            path.display(),
+            expected.display()
+        );
+        checked += 1;
+    }
+
+    assert!(checked > 0);
+}
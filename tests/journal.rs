@@ -0,0 +1,144 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+
+use transactions::{
+    batch,
+    journal::{self, Balances, Entry, Journal, JournalError, Operation},
+    process, Currency, Money, State, Transaction, TransactionType,
+};
+
+mod common;
+use common::transaction;
+
+fn workload() -> Vec<Transaction> {
+    use TransactionType::*;
+
+    vec![
+        transaction(Deposit, 1, 1, Some(100000)),
+        transaction(Withdrawal, 1, 2, Some(30000)),
+        // fails, but still opens an account for client 2
+        transaction(Withdrawal, 2, 3, Some(10000)),
+        transaction(Dispute, 1, 2, None),
+        transaction(Resolve, 1, 2, None),
+        transaction(Deposit, 3, 4, Some(5000)),
+        transaction(Dispute, 3, 4, None),
+        transaction(Chargeback, 3, 4, None),
+        transaction(Deposit, 3, 5, Some(5000)),
+        transaction(Dispute, 1, 1, None),
+    ]
+}
+
+#[test]
+fn journal_records_every_change() {
+    let mut state = State::new();
+    state.journal = Some(Journal::new());
+    for t in workload() {
+        let _ = process::process_one(&mut state, t);
+    }
+
+    let entries = state.journal.as_ref().unwrap().entries();
+    let operations = entries
+        .iter()
+        .map(|e| (e.tx, e.client, e.operation))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        operations,
+        vec![
+            (1, 1, Operation::Open),
+            (1, 1, Operation::Deposit),
+            (2, 1, Operation::Withdraw),
+            (3, 2, Operation::Open),
+            (2, 1, Operation::Release),
+            (2, 1, Operation::Hold),
+            (4, 3, Operation::Open),
+            (4, 3, Operation::Deposit),
+            (4, 3, Operation::Hold),
+            (4, 3, Operation::Chargeback),
+            (1, 1, Operation::Hold),
+        ]
+    );
+    assert_eq!(
+        entries[9],
+        Entry {
+            tx: 4,
+            client: 3,
+            r#type: TransactionType::Chargeback,
+            operation: Operation::Chargeback,
+            amount: Money::from_units(5000),
+            currency: Currency::DEFAULT,
+            before: Balances {
+                available: Money::from_units(0),
+                held: Money::from_units(5000),
+                total: Money::from_units(5000),
+                locked: false,
+            },
+            after: Balances {
+                available: Money::from_units(0),
+                held: Money::from_units(0),
+                total: Money::from_units(0),
+                locked: true,
+            },
+            reason: None,
+        }
+    );
+}
+
+#[test]
+fn replayed_journal_rebuilds_state() {
+    let path =
+        std::env::temp_dir().join(format!("transactions-test-journal-{}", std::process::id()));
+    let mut state = State::new();
+    let f = File::create(&path).unwrap();
+    state.journal = Some(Journal::with_sink(Box::new(BufWriter::new(f))));
+    for t in workload() {
+        let _ = process::process_one(&mut state, t);
+    }
+    state.journal.as_mut().unwrap().flush().unwrap();
+
+    let mut replayed = State::new();
+    journal::replay(BufReader::new(File::open(&path).unwrap()), &mut replayed).unwrap();
+    assert_eq!(replayed.accounts, state.accounts);
+    assert_eq!(replayed.transactions, state.transactions);
+
+    // replaying on top of a state that has moved on is caught
+    let mut diverged = State::new();
+    process::process_one(
+        &mut diverged,
+        transaction(TransactionType::Deposit, 1, 9, Some(1)),
+    )
+    .unwrap();
+    assert!(matches!(
+        journal::replay(BufReader::new(File::open(&path).unwrap()), &mut diverged),
+        Err(JournalError::Diverged { tx: 1, client: 1 })
+    ));
+
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn rolled_back_batch_leaves_no_entries() {
+    let mut state = State::new();
+    state.journal = Some(Journal::new());
+    process::process_one(
+        &mut state,
+        transaction(TransactionType::Deposit, 1, 1, Some(10000)),
+    )
+    .unwrap();
+
+    batch::process_batch(
+        &mut state,
+        vec![
+            transaction(TransactionType::Deposit, 2, 2, Some(10000)),
+            transaction(TransactionType::Withdrawal, 1, 3, Some(20000)),
+        ],
+    )
+    .unwrap_err();
+    assert_eq!(state.journal.as_ref().unwrap().entries().len(), 2);
+
+    batch::process_batch(
+        &mut state,
+        vec![transaction(TransactionType::Deposit, 2, 2, Some(10000))],
+    )
+    .unwrap();
+    assert_eq!(state.journal.as_ref().unwrap().entries().len(), 4);
+}
@@ -0,0 +1,141 @@
+use transactions::{
+    process::{self, Policy, TransactionProcessingError},
+    Account, Balance, Money, State, Transaction, TransactionStatus, TransactionType,
+};
+
+fn process(
+    state: &mut State,
+    r#type: TransactionType,
+    amount: Option<i64>,
+) -> Result<(), TransactionProcessingError> {
+    process::process_one(
+        state,
+        Transaction::new(r#type, 1, 1, amount.map(Money::from_units)),
+    )
+}
+
+#[test]
+fn dispute_twice() {
+    let mut state = State::new();
+
+    process(&mut state, TransactionType::Deposit, Some(100000)).unwrap();
+    process(&mut state, TransactionType::Dispute, None).unwrap();
+    assert_eq!(
+        process(&mut state, TransactionType::Dispute, None),
+        Err(TransactionProcessingError::IllegalTransition {
+            from: TransactionStatus::Disputed,
+            to: TransactionStatus::Disputed,
+        })
+    );
+
+    assert_eq!(
+        state.accounts.get(&1).unwrap(),
+        &Account::with_balance(
+            1,
+            Balance {
+                available: Money::from_units(0),
+                held: Money::from_units(100000),
+                total: Money::from_units(100000)
+            },
+            false
+        )
+    );
+}
+
+#[test]
+fn resolve_undisputed() {
+    let mut state = State::new();
+
+    process(&mut state, TransactionType::Deposit, Some(100000)).unwrap();
+    assert_eq!(
+        process(&mut state, TransactionType::Resolve, None),
+        Err(TransactionProcessingError::IllegalTransition {
+            from: TransactionStatus::Processed,
+            to: TransactionStatus::Resolved,
+        })
+    );
+    assert_eq!(
+        process(&mut state, TransactionType::Chargeback, None),
+        Err(TransactionProcessingError::IllegalTransition {
+            from: TransactionStatus::Processed,
+            to: TransactionStatus::ChargedBack,
+        })
+    );
+}
+
+#[test]
+fn redispute_resolved_allowed_by_default() {
+    let mut state = State::new();
+
+    process(&mut state, TransactionType::Deposit, Some(100000)).unwrap();
+    process(&mut state, TransactionType::Dispute, None).unwrap();
+    process(&mut state, TransactionType::Resolve, None).unwrap();
+    process(&mut state, TransactionType::Dispute, None).unwrap();
+
+    assert_eq!(
+        state.transactions.get(&1).unwrap().status,
+        TransactionStatus::Disputed
+    );
+    assert_eq!(
+        state.accounts.get(&1).unwrap(),
+        &Account::with_balance(
+            1,
+            Balance {
+                available: Money::from_units(0),
+                held: Money::from_units(100000),
+                total: Money::from_units(100000)
+            },
+            false
+        )
+    );
+}
+
+#[test]
+fn redispute_resolved_disallowed_by_policy() {
+    let mut state = State::with_policy(Policy {
+        allow_redispute: false,
+        ..Policy::default()
+    });
+
+    process(&mut state, TransactionType::Deposit, Some(100000)).unwrap();
+    process(&mut state, TransactionType::Dispute, None).unwrap();
+    process(&mut state, TransactionType::Resolve, None).unwrap();
+    assert_eq!(
+        process(&mut state, TransactionType::Dispute, None),
+        Err(TransactionProcessingError::IllegalTransition {
+            from: TransactionStatus::Resolved,
+            to: TransactionStatus::Disputed,
+        })
+    );
+
+    assert_eq!(
+        state.transactions.get(&1).unwrap().status,
+        TransactionStatus::Resolved
+    );
+    assert_eq!(
+        state.accounts.get(&1).unwrap(),
+        &Account::with_balance(
+            1,
+            Balance {
+                available: Money::from_units(100000),
+                held: Money::from_units(0),
+                total: Money::from_units(100000)
+            },
+            false
+        )
+    );
+}
+
+#[test]
+fn chargeback_is_final() {
+    let mut state = State::new();
+
+    process(&mut state, TransactionType::Deposit, Some(100000)).unwrap();
+    process(&mut state, TransactionType::Dispute, None).unwrap();
+    process(&mut state, TransactionType::Chargeback, None).unwrap();
+
+    assert_eq!(
+        state.transactions.get(&1).unwrap().status,
+        TransactionStatus::ChargedBack
+    );
+}
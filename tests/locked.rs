@@ -1,4 +1,30 @@
-use transactions::{process, Account, State, Transaction, TransactionType};
+use transactions::{
+    audit,
+    process::{self, LockedAccounts, Policy, TransactionProcessingError},
+    Account, Balance, Currency, Money, State, Transaction, TransactionType,
+};
+
+mod common;
+use common::process;
+
+fn state(locked_accounts: LockedAccounts) -> State {
+    State::with_policy(Policy {
+        audit: true,
+        locked_accounts,
+        ..Policy::default()
+    })
+}
+
+// client 1's account, locked by a chargeback with tx 2 still disputed
+const LOCKED: &str = "\
+type,client,tx,amount
+deposit,1,1,10.0
+deposit,1,2,5.0
+deposit,1,3,1.0
+dispute,1,2,
+dispute,1,3,
+chargeback,1,3,
+";
 
 #[test]
 fn locked_account_rejects_future_activity() {
@@ -6,58 +32,141 @@ fn locked_account_rejects_future_activity() {
 
     process::process_one(
         &mut state,
-        Transaction {
-            r#type: TransactionType::Deposit,
-            amount: Some(100000),
-            client: 1,
-            tx: 1,
-            disputed: false,
-        },
+        Transaction::new(
+            TransactionType::Deposit,
+            1,
+            1,
+            Some(Money::from_units(100000)),
+        ),
     )
     .unwrap();
     process::process_one(
         &mut state,
-        Transaction {
-            r#type: TransactionType::Dispute,
-            amount: None,
-            client: 1,
-            tx: 1,
-            disputed: false,
-        },
+        Transaction::new(TransactionType::Dispute, 1, 1, None),
     )
     .unwrap();
     process::process_one(
         &mut state,
-        Transaction {
-            r#type: TransactionType::Chargeback,
-            amount: None,
-            client: 1,
-            tx: 1,
-            disputed: false,
-        },
+        Transaction::new(TransactionType::Chargeback, 1, 1, None),
     )
     .unwrap();
 
     assert!(process::process_one(
         &mut state,
-        Transaction {
-            r#type: TransactionType::Deposit,
-            amount: Some(100000),
-            client: 1,
-            tx: 1,
-            disputed: false,
-        },
+        Transaction::new(
+            TransactionType::Deposit,
+            1,
+            1,
+            Some(Money::from_units(100000))
+        ),
     )
     .is_err());
 
     assert_eq!(
         state.accounts.get(&1).unwrap(),
-        &Account {
-            id: 1,
-            available: 0,
-            held: 0,
-            total: 0,
-            locked: true
-        }
+        &Account::with_balance(
+            1,
+            Balance {
+                available: Money::from_units(0),
+                held: Money::from_units(0),
+                total: Money::from_units(0)
+            },
+            true
+        )
+    );
+}
+
+#[test]
+fn locked_accounts_can_close_disputes() {
+    let policy = "resolve,chargeback".parse::<LockedAccounts>().unwrap();
+    for (policy, resolved) in [
+        (
+            LockedAccounts::NONE,
+            Err(TransactionProcessingError::AccountLocked),
+        ),
+        (policy, Ok(())),
+    ] {
+        let mut state = state(policy);
+        process(LOCKED, &mut state);
+
+        let results = process(
+            "type,client,tx,amount\n\
+             resolve,1,2,\n\
+             deposit,1,4,1.0\n",
+            &mut state,
+        );
+
+        assert_eq!(
+            results,
+            vec![resolved, Err(TransactionProcessingError::AccountLocked)]
+        );
+        assert!(state.accounts[&1].locked);
+    }
+}
+
+#[test]
+fn locked_accounts_can_take_deposits_but_not_withdrawals() {
+    let mut state = state(LockedAccounts::allowing(&[TransactionType::Deposit]));
+    process(LOCKED, &mut state);
+
+    let results = process(
+        "type,client,tx,amount\n\
+         deposit,1,4,1.0\n\
+         withdrawal,1,5,1.0\n",
+        &mut state,
+    );
+
+    assert_eq!(
+        results,
+        vec![Ok(()), Err(TransactionProcessingError::AccountLocked)]
+    );
+    let account = &state.accounts[&1];
+    assert!(account.locked);
+    assert_eq!(
+        account.balance(Currency::DEFAULT).available,
+        Money::from_units(110000)
+    );
+}
+
+#[test]
+fn transfers_are_checked_against_both_accounts() {
+    for (policy, result) in [
+        (
+            LockedAccounts::allowing(&[TransactionType::Deposit]),
+            Err(TransactionProcessingError::AccountLocked),
+        ),
+        (
+            LockedAccounts::allowing(&[TransactionType::Transfer]),
+            Ok(()),
+        ),
+    ] {
+        let mut state = state(policy);
+        process(LOCKED, &mut state);
+        let results = process(
+            "type,client,tx,amount,to_client\n\
+             deposit,2,4,1.0,\n\
+             transfer,2,5,1.0,1\n",
+            &mut state,
+        );
+
+        assert_eq!(results, vec![Ok(()), result]);
+        audit::check(&state).unwrap();
+    }
+}
+
+#[test]
+fn locked_account_policies_parse() {
+    assert_eq!(
+        "none".parse::<LockedAccounts>().unwrap(),
+        LockedAccounts::NONE
+    );
+    let policy = "resolve, chargeback".parse::<LockedAccounts>().unwrap();
+    assert_eq!(
+        policy,
+        LockedAccounts::allowing(&[TransactionType::Chargeback, TransactionType::Resolve])
     );
+    assert!(policy.allows(TransactionType::Resolve));
+    assert!(!policy.allows(TransactionType::Deposit));
+    assert!("lock".parse::<LockedAccounts>().is_err());
+    assert!("".parse::<LockedAccounts>().is_err());
 }
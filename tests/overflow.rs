@@ -0,0 +1,93 @@
+use transactions::{
+    account::AccountError,
+    process::{self, Policy, TransactionProcessingError, WithdrawalDisputes},
+    Account, Balance, Money, State, TransactionError, TransactionType,
+};
+
+mod common;
+use common::transaction;
+
+#[test]
+fn overflowing_transactions_change_nothing() {
+    let mut state = State::new();
+    let max = i64::MAX;
+    process::process_one(
+        &mut state,
+        transaction(TransactionType::Deposit, 1, 1, Some(max)),
+    )
+    .unwrap();
+
+    let overflow = Err(
+        TransactionProcessingError::TransactionProcessingAccountError {
+            source: AccountError::BalanceOverflow,
+        },
+    );
+    assert_eq!(
+        process::process_one(
+            &mut state,
+            transaction(TransactionType::Deposit, 1, 2, Some(1))
+        ),
+        overflow
+    );
+    assert_eq!(
+        process::process_one(
+            &mut state,
+            transaction(TransactionType::Withdrawal, 1, 3, Some(-1))
+        ),
+        Err(
+            TransactionProcessingError::TransactionProcessingTransactionError {
+                source: TransactionError::TransactionAmountNegative,
+            }
+        )
+    );
+    // neither transaction was stored, so tx 2 is still free
+    assert!(!state.transactions.contains_key(&2));
+    process::process_one(
+        &mut state,
+        transaction(TransactionType::Deposit, 1, 2, Some(0)),
+    )
+    .unwrap();
+}
+
+#[test]
+fn overflowing_chargeback_leaves_the_dispute_open() {
+    let mut state = State::with_policy(Policy {
+        withdrawal_disputes: WithdrawalDisputes::CreditOnChargeback,
+        ..Policy::default()
+    });
+    let max = i64::MAX;
+    for t in [
+        transaction(TransactionType::Deposit, 1, 1, Some(max - 1)),
+        transaction(TransactionType::Withdrawal, 1, 2, Some(1)),
+        transaction(TransactionType::Deposit, 1, 3, Some(2)),
+        transaction(TransactionType::Dispute, 1, 2, None),
+    ] {
+        process::process_one(&mut state, t).unwrap();
+    }
+
+    let disputed = state.transactions[&2];
+    assert_eq!(
+        process::process_one(
+            &mut state,
+            transaction(TransactionType::Chargeback, 1, 2, None)
+        ),
+        Err(
+            TransactionProcessingError::TransactionProcessingAccountError {
+                source: AccountError::BalanceOverflow,
+            }
+        )
+    );
+    assert_eq!(state.transactions[&2], disputed);
+    assert_eq!(
+        state.accounts[&1],
+        Account::with_balance(
+            1,
+            Balance {
+                available: Money::from_units(i64::MAX),
+                held: Money::from_units(0),
+                total: Money::from_units(i64::MAX)
+            },
+            false
+        )
+    );
+}
@@ -0,0 +1,132 @@
+use transactions::{
+    process::{self, TransactionProcessingError},
+    Account, Balance, Money, State, Transaction, TransactionType,
+};
+
+fn deposit_for_client_one(state: &mut State) {
+    process::process_one(
+        state,
+        Transaction::new(
+            TransactionType::Deposit,
+            1,
+            1,
+            Some(Money::from_units(100000)),
+        ),
+    )
+    .unwrap();
+}
+
+#[test]
+fn dispute_other_clients_transaction() {
+    let mut state = State::new();
+
+    deposit_for_client_one(&mut state);
+    assert_eq!(
+        process::process_one(
+            &mut state,
+            Transaction::new(TransactionType::Dispute, 2, 1, None),
+        ),
+        Err(TransactionProcessingError::TransactionClientMismatch)
+    );
+
+    assert_eq!(
+        state.accounts.get(&1).unwrap(),
+        &Account::with_balance(
+            1,
+            Balance {
+                available: Money::from_units(100000),
+                held: Money::from_units(0),
+                total: Money::from_units(100000)
+            },
+            false
+        )
+    );
+    assert_eq!(
+        state.accounts.get(&2).unwrap(),
+        &Account::with_balance(
+            2,
+            Balance {
+                available: Money::from_units(0),
+                held: Money::from_units(0),
+                total: Money::from_units(0)
+            },
+            false
+        )
+    );
+}
+
+#[test]
+fn resolve_other_clients_transaction() {
+    let mut state = State::new();
+
+    deposit_for_client_one(&mut state);
+    process::process_one(
+        &mut state,
+        Transaction::new(TransactionType::Dispute, 1, 1, None),
+    )
+    .unwrap();
+    assert_eq!(
+        process::process_one(
+            &mut state,
+            Transaction::new(TransactionType::Resolve, 2, 1, None),
+        ),
+        Err(TransactionProcessingError::TransactionClientMismatch)
+    );
+
+    assert_eq!(
+        state.accounts.get(&1).unwrap(),
+        &Account::with_balance(
+            1,
+            Balance {
+                available: Money::from_units(0),
+                held: Money::from_units(100000),
+                total: Money::from_units(100000)
+            },
+            false
+        )
+    );
+}
+
+#[test]
+fn chargeback_other_clients_transaction() {
+    let mut state = State::new();
+
+    deposit_for_client_one(&mut state);
+    process::process_one(
+        &mut state,
+        Transaction::new(TransactionType::Dispute, 1, 1, None),
+    )
+    .unwrap();
+    assert_eq!(
+        process::process_one(
+            &mut state,
+            Transaction::new(TransactionType::Chargeback, 2, 1, None),
+        ),
+        Err(TransactionProcessingError::TransactionClientMismatch)
+    );
+
+    assert_eq!(
+        state.accounts.get(&1).unwrap(),
+        &Account::with_balance(
+            1,
+            Balance {
+                available: Money::from_units(0),
+                held: Money::from_units(100000),
+                total: Money::from_units(100000)
+            },
+            false
+        )
+    );
+    assert_eq!(
+        state.accounts.get(&2).unwrap(),
+        &Account::with_balance(
+            2,
+            Balance {
+                available: Money::from_units(0),
+                held: Money::from_units(0),
+                total: Money::from_units(0)
+            },
+            false
+        )
+    );
+}
@@ -0,0 +1,217 @@
+use transactions::{
+    parallel,
+    process::{self, Policy, TransactionProcessingError},
+    Currency, Money, State, Transaction, TransactionError, TransactionType,
+};
+
+mod common;
+use common::Rng;
+
+fn workload(n: u32, clients: u16) -> Vec<Transaction> {
+    let mut rng = Rng(0x2545F4914F6CDD1D);
+    let mut owned: Vec<Vec<u32>> = vec![Vec::new(); clients as usize];
+    let mut transactions = Vec::new();
+
+    for tx in 0..n {
+        let client = (rng.next() % clients as u64) as u16;
+        let earlier = &mut owned[client as usize];
+        let mut to_client = None;
+        let (r#type, tx, amount) = match (rng.next() % 10, earlier.is_empty()) {
+            (0..=5, _) | (_, true) => (TransactionType::Deposit, tx, Some(rng.next() % 1000000)),
+            // a few transfers, which the workers have to stop for
+            (6, _) if rng.next().is_multiple_of(50) => {
+                to_client = Some((rng.next() % clients as u64) as u16);
+                (TransactionType::Transfer, tx, Some(rng.next() % 1000000))
+            }
+            (6..=7, _) => (TransactionType::Withdrawal, tx, Some(rng.next() % 1000000)),
+            (op, _) => {
+                let r#type = match op {
+                    8 => TransactionType::Dispute,
+                    _ if rng.next() & 1 == 0 => TransactionType::Resolve,
+                    _ => TransactionType::Chargeback,
+                };
+                let referenced = earlier[rng.next() as usize % earlier.len()];
+                (r#type, referenced, None)
+            }
+        };
+        if amount.is_some() {
+            earlier.push(tx);
+        }
+
+        transactions.push(Transaction {
+            to_client,
+            ..Transaction::new(
+                r#type,
+                client,
+                tx,
+                amount.map(|units| Money::from_units(units as i64)),
+            )
+        });
+    }
+
+    transactions
+}
+
+#[test]
+fn parallel_matches_sequential() {
+    let transactions = workload(20000, 50);
+
+    let mut sequential = State::new();
+    let mut sequential_errors = Vec::new();
+    for (i, t) in transactions.iter().enumerate() {
+        if let Err(e) = process::process_one(&mut sequential, t.clone()) {
+            sequential_errors.push((i, e));
+        }
+    }
+
+    for workers in 1..=4 {
+        let (state, mut errors) = parallel::process_parallel(
+            transactions.iter().cloned().enumerate(),
+            workers,
+            Policy::default(),
+            None,
+        );
+        errors.sort_by_key(|(i, _)| *i);
+
+        assert_eq!(state.accounts, sequential.accounts);
+        assert_eq!(state.transactions, sequential.transactions);
+        assert_eq!(errors, sequential_errors);
+    }
+}
+
+#[test]
+fn parallel_rejects_ids_reused_across_clients() {
+    let transactions = vec![
+        Transaction::new(
+            TransactionType::Deposit,
+            1,
+            1,
+            Some(Money::from_units(100000)),
+        ),
+        Transaction::new(
+            TransactionType::Deposit,
+            2,
+            1,
+            Some(Money::from_units(100000)),
+        ),
+        Transaction::new(TransactionType::Dispute, 2, 1, None),
+    ];
+
+    let (state, mut errors) = parallel::process_parallel(
+        transactions.into_iter().enumerate(),
+        2,
+        Policy::default(),
+        None,
+    );
+    errors.sort_by_key(|(i, _)| *i);
+
+    assert_eq!(
+        errors,
+        vec![
+            (1, TransactionProcessingError::TransactionAlreadyProcessed),
+            (2, TransactionProcessingError::TransactionClientMismatch),
+        ]
+    );
+    assert_eq!(
+        state.accounts[&1].balance(Currency::DEFAULT).total,
+        Money::from_units(100000)
+    );
+    // as when processed sequentially, the rejected deposit still opened client 2's account
+    assert!(state.accounts[&2].balances.is_empty());
+}
+
+#[test]
+fn parallel_frees_the_ids_of_failed_transactions() {
+    let transaction = |r#type, client, tx, units: Option<i64>| {
+        Transaction::new(r#type, client, tx, units.map(Money::from_units))
+    };
+    let transactions = vec![
+        transaction(TransactionType::Deposit, 1, 1, Some(50000)),
+        transaction(TransactionType::Deposit, 1, 2, Some(30000)),
+        // too much, so its id is free again for the deposits that follow
+        transaction(TransactionType::Withdrawal, 1, 3, Some(1000000)),
+        transaction(TransactionType::Deposit, 1, 3, Some(10000)),
+        transaction(TransactionType::Withdrawal, 2, 4, Some(10000)),
+        transaction(TransactionType::Deposit, 3, 4, Some(10000)),
+        transaction(TransactionType::Dispute, 2, 4, None),
+        transaction(TransactionType::Dispute, 1, 3, None),
+        transaction(TransactionType::Chargeback, 1, 3, None),
+        // taken, but client 1 is locked, which comes first
+        transaction(TransactionType::Deposit, 1, 4, Some(10000)),
+        transaction(TransactionType::Dispute, 3, 1, None),
+    ];
+
+    let mut sequential = State::new();
+    let mut sequential_errors = Vec::new();
+    for (i, t) in transactions.iter().enumerate() {
+        if let Err(e) = process::process_one(&mut sequential, t.clone()) {
+            sequential_errors.push((i, e));
+        }
+    }
+
+    for workers in 1..=4 {
+        let (state, mut errors) = parallel::process_parallel(
+            transactions.iter().cloned().enumerate(),
+            workers,
+            Policy::default(),
+            None,
+        );
+        errors.sort_by_key(|(i, _)| *i);
+
+        assert_eq!(state.accounts, sequential.accounts);
+        assert_eq!(state.transactions, sequential.transactions);
+        assert_eq!(errors, sequential_errors);
+    }
+    assert_eq!(
+        sequential.accounts[&1].balance(Currency::DEFAULT).total,
+        Money::from_units(80000)
+    );
+    assert_eq!(
+        sequential_errors
+            .iter()
+            .map(|(i, e)| (*i, e.kind()))
+            .collect::<Vec<_>>(),
+        vec![
+            (2, "NotEnoughAvailable"),
+            (4, "NotEnoughAvailable"),
+            (6, "TransactionClientMismatch"),
+            (9, "AccountLocked"),
+            (10, "TransactionClientMismatch"),
+        ]
+    );
+}
+
+#[test]
+fn parallel_audits_transfers_between_workers() {
+    let transaction = |r#type, client, tx, units: Option<i64>, to_client| Transaction {
+        to_client,
+        ..Transaction::new(r#type, client, tx, units.map(Money::from_units))
+    };
+    // the transfer is stored by the worker for client 1, and client 2's account is on the other
+    let transactions = vec![
+        transaction(TransactionType::Deposit, 1, 1, Some(100000), None),
+        transaction(TransactionType::Transfer, 1, 2, Some(40000), Some(2)),
+        transaction(TransactionType::Withdrawal, 1, 2, None, None),
+    ];
+    let policy = Policy {
+        audit: true,
+        ..Policy::default()
+    };
+
+    let (state, errors) =
+        parallel::process_parallel(transactions.into_iter().enumerate(), 2, policy, None);
+
+    assert_eq!(
+        errors,
+        vec![(
+            2,
+            TransactionProcessingError::TransactionProcessingTransactionError {
+                source: TransactionError::TransactionNeedsAmount
+            }
+        )]
+    );
+    assert_eq!(
+        state.accounts[&2].balance(Currency::DEFAULT).total,
+        Money::from_units(40000)
+    );
+}
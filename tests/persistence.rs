@@ -0,0 +1,56 @@
+use transactions::{
+    process::{self, Policy},
+    store::{AccountStore, FileAccountStore, FileTransactionStore},
+    Account, Balance, Money, State, Transaction, TransactionType,
+};
+
+#[test]
+fn resume_from_persisted_state() {
+    let dir = std::env::temp_dir().join(format!("transactions-test-state-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let open = || {
+        State::with_stores(
+            FileTransactionStore::open(dir.join("transactions.db")).unwrap(),
+            FileAccountStore::open(dir.join("accounts.log")).unwrap(),
+            Policy::default(),
+        )
+    };
+
+    let mut state = open();
+    process::process_one(
+        &mut state,
+        Transaction::new(
+            TransactionType::Deposit,
+            1,
+            1,
+            Some(Money::from_units(100000)),
+        ),
+    )
+    .unwrap();
+    drop(state);
+
+    // a later run disputes the deposit from the earlier one
+    let mut state = open();
+    process::process_one(
+        &mut state,
+        Transaction::new(TransactionType::Dispute, 1, 1, None),
+    )
+    .unwrap();
+    drop(state);
+
+    let state = open();
+    assert_eq!(
+        state.accounts.get(1).unwrap().unwrap(),
+        Account::with_balance(
+            1,
+            Balance {
+                available: Money::from_units(0),
+                held: Money::from_units(100000),
+                total: Money::from_units(100000)
+            },
+            false
+        )
+    );
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,187 @@
+use proptest::prelude::*;
+use std::collections::HashMap;
+use std::io::Cursor;
+use std::sync::Arc;
+
+use transactions::{
+    audit,
+    input::{self, Format},
+    process::{self, LockedAccounts, Policy, TransactionProcessingError, WithdrawalDisputes},
+    rates::Rate,
+    Currency, Money, State, Transaction, TransactionType,
+};
+
+fn transaction_type() -> impl Strategy<Value = TransactionType> {
+    prop_oneof![
+        Just(TransactionType::Deposit),
+        Just(TransactionType::Withdrawal),
+        Just(TransactionType::Dispute),
+        Just(TransactionType::Resolve),
+        Just(TransactionType::Chargeback),
+        Just(TransactionType::Convert),
+        Just(TransactionType::Transfer),
+        Just(TransactionType::Lock),
+        Just(TransactionType::Unlock),
+    ]
+}
+
+// amounts near the limits as well as ordinary ones, so that overflow is actually reached
+fn amount() -> impl Strategy<Value = Option<Money>> {
+    prop_oneof![
+        Just(None),
+        (0..1000000i64).prop_map(Some),
+        (i64::MAX - 1000000..=i64::MAX).prop_map(Some),
+        any::<i64>().prop_map(Some),
+    ]
+    .prop_map(|units| units.map(Money::from_units))
+}
+
+// disputes and the like mostly leave the currency out, as they would in real input
+fn currency() -> impl Strategy<Value = Currency> {
+    prop_oneof![
+        2 => Just(Currency::DEFAULT),
+        1 => Just("EUR".parse().unwrap()),
+    ]
+}
+
+fn transaction() -> impl Strategy<Value = Transaction> {
+    (
+        transaction_type(),
+        amount(),
+        0..4u16,
+        0..16u32,
+        currency(),
+        prop::option::of(currency()),
+        prop::option::of(0..4u16),
+        prop::option::of("[A-Z]{0,3}"),
+    )
+        .prop_map(
+            |(r#type, amount, client, tx, currency, to_currency, to_client, reason)| Transaction {
+                currency,
+                to_currency,
+                to_client,
+                reason,
+                ..Transaction::new(r#type, client, tx, amount)
+            },
+        )
+}
+
+// rates both ways between the currencies above, one of them large enough to overflow
+fn rates() -> HashMap<(Currency, Currency), Rate> {
+    let eur = "EUR".parse().unwrap();
+    let mut rates = HashMap::new();
+    rates.insert((Currency::DEFAULT, eur), "0.9".parse().unwrap());
+    rates.insert((eur, Currency::DEFAULT), "1000".parse().unwrap());
+
+    rates
+}
+
+fn withdrawal_disputes() -> impl Strategy<Value = WithdrawalDisputes> {
+    prop_oneof![
+        Just(WithdrawalDisputes::Disallow),
+        Just(WithdrawalDisputes::CreditOnChargeback),
+        Just(WithdrawalDisputes::Release),
+    ]
+}
+
+fn locked_accounts() -> impl Strategy<Value = LockedAccounts> {
+    prop::collection::vec(transaction_type(), 0..4)
+        .prop_map(|types| LockedAccounts::allowing(&types))
+}
+
+// a value that is mostly well-formed, so that most rows get past the reader, and sometimes not
+fn mostly(good: &'static str, bad: &'static str) -> impl Strategy<Value = String> {
+    prop_oneof![12 => good, 1 => bad]
+}
+
+// a column that input can leave empty
+fn optional(good: &'static str, bad: &'static str) -> impl Strategy<Value = String> {
+    prop_oneof![Just(String::new()), mostly(good, bad)]
+}
+
+// one CSV row, with every column the reader knows
+fn row() -> impl Strategy<Value = String> {
+    let amount = prop_oneof![
+        2 => Just(String::new()),
+        6 => "[0-9]{1,3}\\.[0-9]{0,4}",
+        1 => "[0-9]{1,25}",
+        1 => "[0-9]{1,20}\\.[0-9]{0,5}",
+        1 => "-?[0-9]{0,3}\\.?[0-9]{0,3}",
+        1 => ".{0,8}",
+    ];
+    (
+        mostly(
+            "deposit|withdrawal|dispute|resolve|chargeback|convert|transfer|lock|unlock",
+            "[a-z]{0,6}",
+        ),
+        mostly("[0-9]", "[0-9]{6}|-1"),
+        mostly("[0-9]{1,2}", "[0-9]{11}"),
+        amount,
+        optional("EUR|eur", "[A-Za-z0-9]{0,9}"),
+        optional("EUR|eur", "[A-Za-z0-9]{0,9}"),
+        optional("[0-9]{1,10}", "[0-9]{21}|-1"),
+        optional("[0-9]", "[0-9]{6}|-1"),
+        optional("REVIEWED|[A-Z]{1,6}", " {0,3}"),
+    )
+        .prop_map(
+            |(r#type, client, tx, amount, currency, to_currency, timestamp, to_client, reason)| {
+                format!(
+                    "{},{},{},{},{},{},{},{},{}",
+                    r#type, client, tx, amount, currency, to_currency, timestamp, to_client, reason
+                )
+            },
+        )
+}
+
+proptest! {
+    #[test]
+    fn processing_never_breaks_invariants(
+        withdrawal_disputes in withdrawal_disputes(),
+        locked_accounts in locked_accounts(),
+        transactions in prop::collection::vec(transaction(), 0..200),
+    ) {
+        let mut state = State::with_policy(Policy {
+            withdrawal_disputes,
+            audit: true,
+            admin: true,
+            locked_accounts,
+            ..Policy::default()
+        });
+        state.rates = Some(Arc::new(rates()));
+
+        for t in transactions {
+            if let Err(e) = process::process_one(&mut state, t) {
+                prop_assert!(
+                    !matches!(e, TransactionProcessingError::InvariantViolated { .. }),
+                    "{}",
+                    e
+                );
+            }
+        }
+        prop_assert!(audit::check(&state).is_ok());
+    }
+
+    #[test]
+    fn no_csv_input_panics(
+        rows in prop::collection::vec(row(), 0..50),
+        garbage in prop::collection::vec(any::<u8>(), 0..64),
+    ) {
+        let mut csv = b"type,client,tx,amount,currency,to_currency,timestamp,to_client,reason\n".to_vec();
+        for row in rows {
+            csv.extend_from_slice(row.as_bytes());
+            csv.push(b'\n');
+        }
+        csv.extend_from_slice(&garbage);
+
+        let mut state = State::with_policy(Policy {
+            audit: true,
+            admin: true,
+            ..Policy::default()
+        });
+        state.rates = Some(Arc::new(rates()));
+        for (_, t) in input::read(Cursor::new(csv), Format::Csv).unwrap().flatten() {
+            let _ = process::process_one(&mut state, t);
+        }
+        prop_assert!(audit::check(&state).is_ok());
+    }
+}
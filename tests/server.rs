@@ -0,0 +1,68 @@
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+use transactions::{server, Account, Balance, Money, State};
+
+// sends `input`, closes the write side, and returns everything the server sent back
+async fn exchange(addr: std::net::SocketAddr, input: &str) -> String {
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream.write_all(input.as_bytes()).await.unwrap();
+    stream.shutdown().await.unwrap();
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    response
+}
+
+#[tokio::test]
+async fn concurrent_csv_and_json_lines() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let state = Arc::new(Mutex::new(State::new()));
+    tokio::spawn(server::serve(listener, state.clone()));
+
+    let csv = exchange(
+        addr,
+        "type,client,tx,amount\ndeposit,1,1,1.5\nwithdrawal,1,2,5.0\n",
+    );
+    let json = exchange(
+        addr,
+        "{\"type\": \"deposit\", \"client\": 2, \"tx\": 3, \"amount\": \"2.25\"}\n\
+         {\"type\": \"dispute\", \"client\": 2, \"tx\": 3}\n\
+         {\"type\": \"deposit\", \"client\": 2}\n",
+    );
+    let (csv, json) = tokio::join!(csv, json);
+
+    assert_eq!(
+        csv,
+        "rejected 3: NotEnoughAvailable: account error: not enough available\n"
+    );
+    assert!(json.starts_with("rejected 3: InvalidRecord: missing field `tx`"));
+
+    let dump = exchange(addr, "!dump\n").await;
+    let mut rows = dump.lines().collect::<Vec<_>>();
+    rows[1..3].sort();
+    assert_eq!(
+        rows,
+        vec![
+            "client,available,held,total,locked",
+            "1,1.5000,0.0000,1.5000,false",
+            "2,0.0000,2.2500,2.2500,false",
+            "",
+        ]
+    );
+
+    assert_eq!(
+        state.lock().unwrap().accounts.get(&1).unwrap(),
+        &Account::with_balance(
+            1,
+            Balance {
+                available: Money::from_units(15000),
+                held: Money::from_units(0),
+                total: Money::from_units(15000)
+            },
+            false
+        )
+    );
+}
@@ -0,0 +1,84 @@
+use transactions::{
+    process,
+    snapshot::{Snapshot, SnapshotError},
+    Account, Balance, Money, State, Transaction, TransactionStatus, TransactionType,
+};
+
+#[test]
+fn snapshot_round_trip() {
+    let mut state = State::new();
+
+    process::process_one(
+        &mut state,
+        Transaction::new(
+            TransactionType::Deposit,
+            1,
+            1,
+            Some(Money::from_units(100000)),
+        ),
+    )
+    .unwrap();
+    process::process_one(
+        &mut state,
+        Transaction::new(
+            TransactionType::Withdrawal,
+            2,
+            2,
+            Some(Money::from_units(10000)),
+        ),
+    )
+    .unwrap_err();
+    process::process_one(
+        &mut state,
+        Transaction::new(TransactionType::Dispute, 1, 1, None),
+    )
+    .unwrap();
+
+    let mut buf = Vec::new();
+    Snapshot::capture(&state).unwrap().write(&mut buf).unwrap();
+    let snapshot = Snapshot::read(buf.as_slice()).unwrap();
+    assert_eq!(snapshot, Snapshot::capture(&state).unwrap());
+
+    let mut restored = State::new();
+    snapshot.restore(&mut restored).unwrap();
+    assert_eq!(restored.accounts, state.accounts);
+    assert_eq!(restored.transactions, state.transactions);
+    assert_eq!(
+        restored.transactions.get(&1).unwrap().status,
+        TransactionStatus::Disputed
+    );
+
+    // the restored state can pick up where the original left off
+    process::process_one(
+        &mut restored,
+        Transaction::new(TransactionType::Resolve, 1, 1, None),
+    )
+    .unwrap();
+
+    assert_eq!(
+        restored.accounts.get(&1).unwrap(),
+        &Account::with_balance(
+            1,
+            Balance {
+                available: Money::from_units(100000),
+                held: Money::from_units(0),
+                total: Money::from_units(100000)
+            },
+            false
+        )
+    );
+}
+
+#[test]
+fn snapshots_only_restore_stored_types() {
+    let json = r#"{"version":2,"accounts":[{"client":1,"available":0,"held":0,"total":0,"locked":false}],"transactions":[{"tx":1,"type":"lock","client":1,"amount":10000,"status":"disputed"}]}"#;
+    let snapshot = Snapshot::read(json.as_bytes()).unwrap();
+
+    let mut state = State::new();
+    assert!(matches!(
+        snapshot.restore(&mut state),
+        Err(SnapshotError::NotStored(1, TransactionType::Lock))
+    ));
+    assert!(state.accounts.is_empty());
+    assert!(state.transactions.is_empty());
+}
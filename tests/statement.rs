@@ -0,0 +1,108 @@
+use transactions::{
+    journal::Journal,
+    output::Format,
+    process::{self, Policy},
+    statement::{self, StatementLine},
+    Currency, Money, State, Transaction, TransactionType,
+};
+
+mod common;
+use common::transaction;
+
+#[test]
+fn statement_has_running_balances() {
+    use TransactionType::*;
+
+    let mut state = State::new();
+    state.journal = Some(Journal::new());
+    for t in [
+        transaction(Deposit, 7, 1, Some(20000)),
+        transaction(Deposit, 8, 2, Some(10000)),
+        transaction(Withdrawal, 7, 3, Some(50000)),
+        transaction(Withdrawal, 7, 4, Some(5000)),
+        transaction(Dispute, 7, 1, None),
+        transaction(Resolve, 7, 1, None),
+    ] {
+        let _ = process::process_one(&mut state, t);
+    }
+
+    let entries = state.journal.as_ref().unwrap().entries();
+    let line = |tx, r#type, amount, available, held: i64| StatementLine {
+        client: 7,
+        tx,
+        r#type,
+        amount: Money::from_units(amount),
+        currency: Currency::DEFAULT,
+        available: Money::from_units(available),
+        held: Money::from_units(held),
+        total: Money::from_units(available + held),
+        locked: false,
+        reason: None,
+    };
+    // the failed withdrawal never changed the account, so it isn't on the statement
+    assert_eq!(
+        statement::statement(entries, 7),
+        vec![
+            line(1, Deposit, 20000, 20000, 0),
+            line(4, Withdrawal, 5000, 15000, 0),
+            line(1, Dispute, 20000, -5000, 20000),
+            line(1, Resolve, 20000, 15000, 0),
+        ]
+    );
+
+    let clients = statement::statements(entries)
+        .iter()
+        .map(|l| l.client)
+        .collect::<Vec<_>>();
+    assert_eq!(clients, vec![7, 7, 7, 7, 8]);
+}
+
+#[test]
+fn statement_gives_the_reason_for_a_lock() {
+    use TransactionType::*;
+
+    let eur = "EUR".parse().unwrap();
+    let mut state = State::with_policy(Policy {
+        admin: true,
+        ..Policy::default()
+    });
+    state.journal = Some(Journal::new());
+    let deposit = Transaction {
+        currency: eur,
+        ..transaction(Deposit, 1, 1, Some(900000))
+    };
+    let lock = Transaction {
+        reason: Some("SANCTIONS".to_string()),
+        ..transaction(Lock, 1, 2, None)
+    };
+    for t in [deposit, lock] {
+        process::process_one(&mut state, t).unwrap();
+    }
+
+    // the lock is shown in the currency the client holds, not the one its row was in
+    let lines = statement::statement(state.journal.as_ref().unwrap().entries(), 1);
+    assert_eq!(
+        lines[1],
+        StatementLine {
+            client: 1,
+            tx: 2,
+            r#type: Lock,
+            amount: Money::ZERO,
+            currency: eur,
+            available: Money::from_units(900000),
+            held: Money::ZERO,
+            total: Money::from_units(900000),
+            locked: true,
+            reason: Some("SANCTIONS".to_string()),
+        }
+    );
+
+    let mut buf = Vec::new();
+    statement::write_statement(&mut buf, &lines, Format::Csv).unwrap();
+    assert_eq!(
+        String::from_utf8(buf).unwrap(),
+        "client,tx,type,amount,currency,available,held,total,locked,reason\n\
+         1,1,deposit,90.0000,EUR,90.0000,0.0000,90.0000,false,\n\
+         1,2,lock,0.0000,EUR,90.0000,0.0000,90.0000,true,SANCTIONS\n"
+    );
+}
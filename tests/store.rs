@@ -0,0 +1,70 @@
+use transactions::{
+    process::{self, Policy, TransactionProcessingError},
+    store::FileTransactionStore,
+    Account, Balance, Money, State, Transaction, TransactionType,
+};
+
+#[test]
+fn file_store_dispute_and_chargeback() {
+    let path = std::env::temp_dir().join(format!("transactions-test-store-{}", std::process::id()));
+    let store = FileTransactionStore::create(&path).unwrap();
+    let mut state = State::with_store(store, Policy::default());
+
+    process::process_one(
+        &mut state,
+        Transaction::new(
+            TransactionType::Deposit,
+            1,
+            1,
+            Some(Money::from_units(100000)),
+        ),
+    )
+    .unwrap();
+    process::process_one(
+        &mut state,
+        Transaction::new(
+            TransactionType::Withdrawal,
+            1,
+            4000000000,
+            Some(Money::from_units(10000)),
+        ),
+    )
+    .unwrap();
+    assert_eq!(
+        process::process_one(
+            &mut state,
+            Transaction::new(
+                TransactionType::Deposit,
+                1,
+                4000000000,
+                Some(Money::from_units(10000))
+            ),
+        ),
+        Err(TransactionProcessingError::TransactionAlreadyProcessed)
+    );
+    process::process_one(
+        &mut state,
+        Transaction::new(TransactionType::Dispute, 1, 1, None),
+    )
+    .unwrap();
+    process::process_one(
+        &mut state,
+        Transaction::new(TransactionType::Chargeback, 1, 1, None),
+    )
+    .unwrap();
+
+    assert_eq!(
+        state.accounts.get(&1).unwrap(),
+        &Account::with_balance(
+            1,
+            Balance {
+                available: Money::from_units(-10000),
+                held: Money::from_units(0),
+                total: Money::from_units(-10000)
+            },
+            true
+        )
+    );
+
+    std::fs::remove_file(path).unwrap();
+}
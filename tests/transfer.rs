@@ -0,0 +1,231 @@
+use transactions::{
+    account::AccountError,
+    audit,
+    batch::{self, BatchError},
+    journal::{self, Journal},
+    process::TransactionProcessingError,
+    snapshot::Snapshot,
+    Account, Balance, Currency, Money, State, Transaction, TransactionError, TransactionType,
+};
+
+mod common;
+use common::{audited, process};
+
+fn balance(state: &State, client: u16) -> Balance {
+    state.accounts[&client].balance(Currency::DEFAULT)
+}
+
+fn funds(available: i64, held: i64) -> Balance {
+    Balance {
+        available: Money::from_units(available),
+        held: Money::from_units(held),
+        total: Money::from_units(available + held),
+    }
+}
+
+const TRANSFER: &str = "\
+type,client,tx,amount,to_client
+deposit,1,1,10.0,
+deposit,2,2,1.0,
+transfer,1,3,4.0,2
+";
+
+#[test]
+fn transfers_move_funds_between_clients() {
+    let mut state = audited();
+    assert_eq!(process(TRANSFER, &mut state), vec![Ok(()), Ok(()), Ok(())]);
+
+    assert_eq!(balance(&state, 1), funds(60000, 0));
+    assert_eq!(balance(&state, 2), funds(50000, 0));
+    assert_eq!(state.transactions[&3].client, 1);
+    assert_eq!(state.transactions[&3].to_client, Some(2));
+}
+
+#[test]
+fn transfers_open_the_account_they_are_to() {
+    let mut state = audited();
+    let results = process(
+        "type,client,tx,amount,to_client\n\
+         deposit,1,1,10.0,\n\
+         transfer,1,2,20.0,3\n\
+         transfer,1,3,4.0,4\n",
+        &mut state,
+    );
+
+    assert_eq!(
+        results[1],
+        Err(
+            TransactionProcessingError::TransactionProcessingAccountError {
+                source: AccountError::NotEnoughAvailable
+            }
+        )
+    );
+    assert_eq!(results[2], Ok(()));
+    // the transfer that failed didn't open an account for client 3
+    assert!(!state.accounts.contains_key(&3));
+    assert_eq!(balance(&state, 4), funds(40000, 0));
+}
+
+#[test]
+fn bad_transfers_change_nothing() {
+    let mut state = audited();
+    process(TRANSFER, &mut state);
+    let results = process(
+        "type,client,tx,amount,to_client\n\
+         transfer,1,4,1.0,\n\
+         transfer,1,5,1.0,1\n\
+         transfer,2,3,1.0,1\n",
+        &mut state,
+    );
+
+    assert_eq!(
+        results,
+        vec![
+            Err(
+                TransactionProcessingError::TransactionProcessingTransactionError {
+                    source: TransactionError::TransactionNeedsTargetClient
+                }
+            ),
+            Err(
+                TransactionProcessingError::TransactionProcessingTransactionError {
+                    source: TransactionError::TransactionTransfersToSameClient
+                }
+            ),
+            Err(TransactionProcessingError::TransactionAlreadyProcessed),
+        ]
+    );
+    assert_eq!(balance(&state, 1), funds(60000, 0));
+    assert_eq!(balance(&state, 2), funds(50000, 0));
+}
+
+#[test]
+fn transfers_respect_locks_on_either_side() {
+    for locked in [1, 2] {
+        let mut state = audited();
+        process(TRANSFER, &mut state);
+        let mut account = state.accounts[&locked].clone();
+        account.locked = true;
+        state.accounts.insert(locked, account);
+
+        assert_eq!(
+            process(
+                "type,client,tx,amount,to_client\ntransfer,1,4,1.0,2\n",
+                &mut state
+            ),
+            vec![Err(TransactionProcessingError::AccountLocked)]
+        );
+        assert_eq!(balance(&state, 1), funds(60000, 0));
+        assert_eq!(balance(&state, 2), funds(50000, 0));
+    }
+}
+
+#[test]
+fn disputes_hold_what_was_transferred() {
+    let mut state = audited();
+    process(TRANSFER, &mut state);
+
+    // only the client who made the transfer can dispute it
+    assert_eq!(
+        process("type,client,tx\ndispute,2,3\n", &mut state),
+        vec![Err(TransactionProcessingError::TransactionClientMismatch)]
+    );
+    assert_eq!(
+        process("type,client,tx\ndispute,1,3\n", &mut state),
+        vec![Ok(())]
+    );
+    assert_eq!(balance(&state, 1), funds(60000, 0));
+    assert_eq!(balance(&state, 2), funds(10000, 40000));
+
+    process("type,client,tx\nresolve,1,3\n", &mut state);
+    assert_eq!(balance(&state, 2), funds(50000, 0));
+    audit::check(&state).unwrap();
+}
+
+#[test]
+fn chargebacks_reverse_both_sides() {
+    let mut state = audited();
+    process(TRANSFER, &mut state);
+    let results = process(
+        "type,client,tx\n\
+         dispute,1,3\n\
+         chargeback,1,3\n",
+        &mut state,
+    );
+
+    assert_eq!(results, vec![Ok(()), Ok(())]);
+    assert_eq!(balance(&state, 1), funds(100000, 0));
+    assert_eq!(balance(&state, 2), funds(10000, 0));
+    // the account the funds were taken back from is the one locked
+    assert!(!state.accounts[&1].locked);
+    assert!(state.accounts[&2].locked);
+    audit::check(&state).unwrap();
+}
+
+#[test]
+fn batches_roll_back_both_sides() {
+    let mut state = audited();
+    process(TRANSFER, &mut state);
+    let accounts = state.accounts.clone();
+
+    let transfer = |tx, to_client, amount| Transaction {
+        to_client: Some(to_client),
+        ..Transaction::new(
+            TransactionType::Transfer,
+            1,
+            tx,
+            Some(Money::from_units(amount)),
+        )
+    };
+    // the last transfer is more than client 1 has left
+    let result = batch::process_batch(
+        &mut state,
+        vec![
+            transfer(4, 2, 10000),
+            transfer(5, 3, 10000),
+            transfer(6, 2, 1000000),
+        ],
+    );
+
+    assert!(matches!(
+        result,
+        Err(BatchError::TransactionFailed { index: 2, .. })
+    ));
+    assert_eq!(state.accounts, accounts);
+    assert!(!state.transactions.contains_key(&4));
+    assert!(!state.accounts.contains_key(&3));
+}
+
+#[test]
+fn snapshots_and_journals_keep_transfers() {
+    let mut state = audited();
+    state.journal = Some(Journal::new());
+    process(TRANSFER, &mut state);
+    process(
+        "type,client,tx,amount,to_client\n\
+         transfer,2,4,2.0,5\n\
+         dispute,1,3,,\n",
+        &mut state,
+    );
+
+    let mut restored = State::new();
+    Snapshot::capture(&state)
+        .unwrap()
+        .restore(&mut restored)
+        .unwrap();
+    assert_eq!(restored.accounts, state.accounts);
+    assert_eq!(restored.transactions, state.transactions);
+
+    let mut buf = Vec::new();
+    for entry in state.journal.as_ref().unwrap().entries() {
+        serde_json::to_writer(&mut buf, entry).unwrap();
+        buf.push(b'\n');
+    }
+    let mut replayed = State::new();
+    journal::replay(buf.as_slice(), &mut replayed).unwrap();
+    assert_eq!(replayed.accounts, state.accounts);
+    assert_eq!(replayed.transactions, state.transactions);
+    assert_eq!(
+        replayed.accounts[&5],
+        Account::with_balance(5, funds(20000, 0), false)
+    );
+}
@@ -0,0 +1,154 @@
+use transactions::{
+    process::{self, Policy, TransactionProcessingError, WithdrawalDisputes},
+    Account, Balance, Currency, Money, State, Transaction, TransactionType,
+};
+
+mod common;
+use common::{transaction, Rng};
+
+fn state(withdrawal_disputes: WithdrawalDisputes) -> State {
+    let mut state = State::with_policy(Policy {
+        withdrawal_disputes,
+        ..Policy::default()
+    });
+    process::process_one(
+        &mut state,
+        transaction(TransactionType::Deposit, 1, 1, Some(100000)),
+    )
+    .unwrap();
+    process::process_one(
+        &mut state,
+        transaction(TransactionType::Withdrawal, 1, 2, Some(30000)),
+    )
+    .unwrap();
+
+    state
+}
+
+fn account(available: i64, held: i64, total: i64, locked: bool) -> Account {
+    Account::with_balance(
+        1,
+        Balance {
+            available: Money::from_units(available),
+            held: Money::from_units(held),
+            total: Money::from_units(total),
+        },
+        locked,
+    )
+}
+
+#[test]
+fn disallow() {
+    let mut state = state(WithdrawalDisputes::Disallow);
+
+    assert_eq!(
+        process::process_one(
+            &mut state,
+            transaction(TransactionType::Dispute, 1, 2, None)
+        ),
+        Err(TransactionProcessingError::WithdrawalDisputeNotAllowed)
+    );
+    assert_eq!(state.accounts[&1], account(70000, 0, 70000, false));
+
+    // deposits can still be disputed
+    process::process_one(
+        &mut state,
+        transaction(TransactionType::Dispute, 1, 1, None),
+    )
+    .unwrap();
+    assert_eq!(state.accounts[&1], account(-30000, 100000, 70000, false));
+}
+
+#[test]
+fn credit_on_chargeback() {
+    let mut state = state(WithdrawalDisputes::CreditOnChargeback);
+
+    process::process_one(
+        &mut state,
+        transaction(TransactionType::Dispute, 1, 2, None),
+    )
+    .unwrap();
+    assert_eq!(state.accounts[&1], account(70000, 0, 70000, false));
+    process::process_one(
+        &mut state,
+        transaction(TransactionType::Resolve, 1, 2, None),
+    )
+    .unwrap();
+    assert_eq!(state.accounts[&1], account(70000, 0, 70000, false));
+
+    process::process_one(
+        &mut state,
+        transaction(TransactionType::Dispute, 1, 2, None),
+    )
+    .unwrap();
+    process::process_one(
+        &mut state,
+        transaction(TransactionType::Chargeback, 1, 2, None),
+    )
+    .unwrap();
+    assert_eq!(state.accounts[&1], account(100000, 0, 100000, true));
+}
+
+#[test]
+fn release() {
+    let mut state = state(WithdrawalDisputes::Release);
+
+    process::process_one(
+        &mut state,
+        transaction(TransactionType::Dispute, 1, 2, None),
+    )
+    .unwrap();
+    assert_eq!(state.accounts[&1], account(100000, -30000, 70000, false));
+    process::process_one(
+        &mut state,
+        transaction(TransactionType::Chargeback, 1, 2, None),
+    )
+    .unwrap();
+    assert_eq!(state.accounts[&1], account(100000, 0, 100000, true));
+}
+
+#[test]
+fn held_never_goes_negative() {
+    for policy in [
+        WithdrawalDisputes::Disallow,
+        WithdrawalDisputes::CreditOnChargeback,
+    ] {
+        let mut rng = Rng(0x2545F4914F6CDD1D);
+        let mut state = State::with_policy(Policy {
+            withdrawal_disputes: policy,
+            ..Policy::default()
+        });
+
+        for tx in 0..10000 {
+            let client = (rng.next() % 8) as u16;
+            let earlier = (rng.next() % (tx as u64 + 1)) as u32;
+            let t = match rng.next() % 6 {
+                0 => (TransactionType::Deposit, tx, Some(rng.next() % 1000000)),
+                1 => (TransactionType::Withdrawal, tx, Some(rng.next() % 1000000)),
+                2 => (TransactionType::Dispute, earlier, None),
+                3 => (TransactionType::Resolve, earlier, None),
+                4 => (TransactionType::Chargeback, earlier, None),
+                _ => (TransactionType::Dispute, earlier, None),
+            };
+            let _ = process::process_one(
+                &mut state,
+                Transaction::new(
+                    t.0,
+                    client,
+                    t.1,
+                    t.2.map(|units| Money::from_units(units as i64)),
+                ),
+            );
+
+            for account in state.accounts.values() {
+                assert!(
+                    !account.balance(Currency::DEFAULT).held.is_negative(),
+                    "{:?}: {:?} after tx {}",
+                    policy,
+                    account,
+                    tx
+                );
+            }
+        }
+    }
+}